@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::config::{Traverse, read_dir_config};
+use crate::error::IoContext;
+use crate::{
+    Action, ConflictKind, Ctx, Dotr, Error, Outcome, Plan, PlanEntry, Report, Result, points_to,
+};
+
+impl Dotr {
+    /// Plan adopting a single file or symlink at `dst` into `src`
+    ///
+    /// Unless `link` is false, `dst` is then linked back to `src`; otherwise
+    /// it's removed, to make way for its directory to be linked as a whole.
+    fn plan_adopt_file(&self, dst: &Path, src: &Path, link: bool) -> Result<PlanEntry> {
+        let dst_type = dst
+            .symlink_metadata()
+            .at(dst, "read metadata of")?
+            .file_type();
+        let src_metadata = src.symlink_metadata().ok();
+
+        if dst_type.is_symlink() {
+            // Symlink sources are duplicated as-is by `link`, so adopting
+            // one only requires a copy of it in the source
+            let target = dst.read_link().at(dst, "read link")?;
+            let entry = if points_to(dst, src)? {
+                debug!(src = %src.display(), dst = %dst.display(), "Destination already points to the source");
+                PlanEntry::new(src, dst, Outcome::AlreadyCorrect)
+            } else {
+                match src_metadata {
+                    None => {
+                        let mut entry = PlanEntry::new(src, dst, Outcome::Adopted);
+                        let parent = src.parent().unwrap();
+                        if parent.symlink_metadata().is_err() {
+                            entry = entry.step(
+                                Action::CreateDirAll {
+                                    path: parent.to_owned(),
+                                },
+                                "parent directory missing",
+                            );
+                        }
+                        entry.step(
+                            Action::CreateSymlink {
+                                target,
+                                path: src.to_owned(),
+                            },
+                            "copying symlink into the source",
+                        )
+                    }
+                    Some(meta)
+                        if meta.file_type().is_symlink()
+                            && src.read_link().at(src, "read link")? == target =>
+                    {
+                        debug!(src = %src.display(), dst = %dst.display(), "Source is already the same symlink");
+                        PlanEntry::new(src, dst, Outcome::AlreadyCorrect)
+                    }
+                    Some(_) => return Err(different_source(src, dst)),
+                }
+            };
+            return Ok(if link {
+                entry
+            } else {
+                entry.step(
+                    Action::RemoveFile {
+                        path: dst.to_owned(),
+                    },
+                    "directory to be linked as a whole",
+                )
+            });
+        }
+
+        let entry = match src_metadata {
+            None => PlanEntry::new(src, dst, Outcome::Adopted).step(
+                Action::Adopt {
+                    path: dst.to_owned(),
+                    src: src.to_owned(),
+                },
+                "source missing",
+            ),
+            Some(meta)
+                if meta.file_type().is_file()
+                    && fs::read(src).at(src, "read")? == fs::read(dst).at(dst, "read")? =>
+            {
+                PlanEntry::new(src, dst, Outcome::Adopted).step(
+                    Action::RemoveFile {
+                        path: dst.to_owned(),
+                    },
+                    "destination identical to the source",
+                )
+            }
+            Some(_) => return Err(different_source(src, dst)),
+        };
+        Ok(if link {
+            entry.step(
+                Action::CreateSymlink {
+                    target: self.symlink_target(src, dst),
+                    path: dst.to_owned(),
+                },
+                "destination moved to the source",
+            )
+        } else {
+            entry
+        })
+    }
+
+    /// Plan adopting all the content of directory `dst` into `src`
+    ///
+    /// Files are linked back individually, unless `src` is configured with
+    /// `traverse = "link"`, in which case `dst` is replaced with a link to
+    /// the whole directory.
+    fn plan_adopt_dir(&self, ctx: &Ctx, plan: &mut Plan, dst: &Path, src: &Path) -> Result<()> {
+        let link_whole = src.is_dir() && read_dir_config(src)?.traverse == Some(Traverse::Link);
+        let mut dirs = vec![];
+
+        for entry in WalkDir::new(dst).sort_by_file_name() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("Error walking: {}", e);
+                    continue;
+                }
+            };
+            if entry.file_type().is_dir() {
+                dirs.push(entry.path().to_owned());
+                continue;
+            }
+            let entry_src = src.join(ctx.src_rel(entry.path().strip_prefix(dst).unwrap()));
+            plan.push(self.plan_adopt_file(entry.path(), &entry_src, !link_whole)?);
+        }
+
+        if link_whole {
+            let mut entry = PlanEntry::new(src, dst, Outcome::Created);
+            for dir in dirs.into_iter().rev() {
+                entry = entry.step(
+                    Action::RemoveDir { path: dir },
+                    "content moved to the source",
+                );
+            }
+            plan.push(entry.step(
+                Action::CreateSymlink {
+                    target: self.symlink_target(src, dst),
+                    path: dst.to_owned(),
+                },
+                "directory linked per .dotr traverse=link",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Compute everything [`Dotr::adopt`] would do, without touching the
+    /// source or the destination
+    pub fn plan_adopt(&self, src_base: &Path, dst_base: &Path, paths: &[PathBuf]) -> Result<Plan> {
+        info!(src = %src_base.display(), dst = %dst_base.display(), "Planning adopt operation");
+
+        let ctx = Ctx::new(self, src_base, dst_base)?;
+
+        let mut plan = self.new_plan(ctx.state_file.clone());
+        for path in paths {
+            // Resolve the parent only, as `path` itself might be a symlink
+            let file_name = path.file_name().ok_or_else(|| Error::NotAdoptable {
+                path: path.clone(),
+                reason: "not a file or directory name",
+            })?;
+            let dst = std::path::absolute(path)
+                .at(path, "resolve")?
+                .parent()
+                .unwrap()
+                .canonicalize()
+                .at(path, "resolve")?
+                .join(file_name);
+
+            if dst.starts_with(&ctx.src_base) {
+                return Err(Error::NotAdoptable {
+                    path: dst,
+                    reason: "already in the source",
+                });
+            }
+            let Ok(rel) = dst.strip_prefix(&ctx.dst_base) else {
+                return Err(Error::NotAdoptable {
+                    path: dst,
+                    reason: "not inside the destination",
+                });
+            };
+            let src = ctx.src_base.join(ctx.src_rel(rel));
+
+            if dst
+                .symlink_metadata()
+                .at(&dst, "read metadata of")?
+                .file_type()
+                .is_dir()
+            {
+                self.plan_adopt_dir(&ctx, &mut plan, &dst, &src)?;
+            } else {
+                plan.push(self.plan_adopt_file(&dst, &src, true)?);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Move existing destination files or directories into the source and
+    /// link them back
+    ///
+    /// Refuses (before making any changes) if a source file already exists
+    /// with a different content.
+    pub fn adopt(&self, src_base: &Path, dst_base: &Path, paths: &[PathBuf]) -> Result<Report> {
+        self.execute(self.plan_adopt(src_base, dst_base, paths)?)
+    }
+}
+
+fn different_source(src: &Path, dst: &Path) -> Error {
+    Error::Conflict {
+        src: src.to_owned(),
+        dst: dst.to_owned(),
+        kind: ConflictKind::SourceDiffers,
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::error::IoContext;
+use crate::{
+    Action, ConflictKind, Dotr, Error, Outcome, Plan, PlanEntry, Report, Result, resolve_dst_base,
+};
+
+/// Directory (relative to the destination base) holding all the backups
+pub const BACKUP_DIR: &str = ".dotr-backup";
+
+/// Id of a new backup in `dst_base`
+///
+/// Backups are named after the (unix) time they were taken at, so they sort
+/// chronologically, numbered (`<time>.<n>`) if there's already one from the
+/// same second.
+pub(crate) fn new_backup_id(dst_base: &Path) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs();
+    let backup_root = dst_base.join(BACKUP_DIR);
+    (0..)
+        .map(|n| match n {
+            0 => time.to_string(),
+            n => format!("{time}.{n}"),
+        })
+        .find(|id| backup_root.join(id).symlink_metadata().is_err())
+        .unwrap()
+}
+
+/// Where `dst` is to be moved when backed up, keeping its path relative to
+/// `dst_base`
+///
+/// `None` if `dst` is not inside `dst_base` (e.g. due to a `.dotr` `target`),
+/// as it could not be restored.
+pub(crate) fn backup_path(dst_base: &Path, backup_id: &str, dst: &Path) -> Option<PathBuf> {
+    let rel = dst.strip_prefix(dst_base).ok()?;
+    Some(dst_base.join(BACKUP_DIR).join(backup_id).join(rel))
+}
+
+/// Remove the directories left empty after moving `path` out of a backup, up
+/// to (but not including) [`BACKUP_DIR`] itself
+pub(crate) fn remove_empty_parents(path: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir.file_name().is_none_or(|name| name == BACKUP_DIR) {
+            break;
+        }
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// The most recent backup in `backup_root`, if any
+fn latest_backup_id(backup_root: &Path) -> Result<Option<String>> {
+    if !backup_root.exists() {
+        return Ok(None);
+    }
+    let mut latest: Option<((u64, u64), String)> = None;
+    for entry in fs::read_dir(backup_root).at(backup_root, "read directory")? {
+        let name = entry
+            .at(backup_root, "read directory")?
+            .file_name()
+            .to_string_lossy()
+            .into_owned();
+        let (time, n) = name.split_once('.').unwrap_or((&name, "0"));
+        let (Ok(time), Ok(n)) = (time.parse::<u64>(), n.parse::<u64>()) else {
+            continue;
+        };
+        if latest
+            .as_ref()
+            .is_none_or(|(latest, _)| *latest < (time, n))
+        {
+            latest = Some(((time, n), name));
+        }
+    }
+    Ok(latest.map(|(_, name)| name))
+}
+
+impl Dotr {
+    /// Compute everything [`Dotr::restore`] would do, without touching the
+    /// destination
+    ///
+    /// Restores the backup with the given id, or the most recent one.
+    pub fn plan_restore(&self, dst_base: &Path, backup_id: Option<&str>) -> Result<Plan> {
+        let dst_base = resolve_dst_base(dst_base)?;
+        let backup_root = dst_base.join(BACKUP_DIR);
+        let backup_id = match backup_id {
+            Some(id) => id.to_owned(),
+            None => latest_backup_id(&backup_root)?.ok_or_else(|| Error::NoBackups {
+                path: backup_root.clone(),
+            })?,
+        };
+        let backup_dir = backup_root.join(&backup_id);
+        if !backup_dir.is_dir() {
+            return Err(Error::BackupMissing { path: backup_dir });
+        }
+        info!(backup = %backup_dir.display(), dst = %dst_base.display(), "Planning restore operation");
+
+        let mut plan = self.new_plan(None);
+        let mut iter = WalkDir::new(&backup_dir).sort_by_file_name().into_iter();
+        while let Some(entry) = iter.next() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("Error walking: {}", e);
+                    continue;
+                }
+            };
+            let backup = entry.path();
+            let dst = dst_base.join(backup.strip_prefix(&backup_dir).unwrap());
+            if entry.file_type().is_dir() {
+                // Directories backed up as a whole (see `Dotr::set_force_dirs`)
+                // are restored as a whole, over whatever replaced them
+                if !dst.symlink_metadata().is_ok_and(|meta| !meta.is_dir()) {
+                    continue;
+                }
+                iter.skip_current_dir();
+            }
+            let restore = |reason| {
+                PlanEntry::new(backup, &dst, Outcome::Restored).step(
+                    Action::RestoreBackup {
+                        backup: backup.to_owned(),
+                        path: dst.clone(),
+                    },
+                    reason,
+                )
+            };
+
+            plan.push(match dst.symlink_metadata() {
+                Err(_) => restore("destination missing"),
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    restore("destination is a symlink")
+                }
+                Ok(meta) if self.force && !meta.file_type().is_dir() => {
+                    debug!(backup = %backup.display(), dst = %dst.display(), "Force removing destination");
+                    PlanEntry::new(backup, &dst, Outcome::Restored)
+                        .step(
+                            Action::RemoveFile { path: dst.clone() },
+                            "forced: destination already exists",
+                        )
+                        .step(
+                            Action::RestoreBackup {
+                                backup: backup.to_owned(),
+                                path: dst.clone(),
+                            },
+                            "destination removed",
+                        )
+                }
+                Ok(meta) => {
+                    warn!(backup = %backup.display(), dst = %dst.display(), "Destination already exists and is not a symlink");
+                    let kind = if meta.is_dir() {
+                        ConflictKind::DirectoryInTheWay
+                    } else {
+                        ConflictKind::NotOurs
+                    };
+                    PlanEntry::conflict(backup, &dst, kind)
+                }
+            });
+        }
+
+        Ok(plan)
+    }
+
+    /// Move files from a backup (see [`Dotr::set_backup`]) back into the
+    /// destination
+    pub fn restore(&self, dst_base: &Path, backup_id: Option<&str>) -> Result<Report> {
+        self.execute(self.plan_restore(dst_base, backup_id)?)
+    }
+}
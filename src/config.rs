@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{error, fmt, fs, io};
+
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::error::IoContext;
+use crate::exclude::dir_matcher;
+use crate::{Dotr, Error, Result, should_traverse};
+
+pub(crate) const DOTR_CONFIG_FILE: &str = ".dotr";
+
+#[derive(serde::Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Traverse {
+    Link,
+}
+
+/// How files are installed at their destination
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Symlink pointing to the source
+    #[default]
+    Symlink,
+    /// Copy of the source, tracked so it's only removed if left unmodified
+    Copy,
+    /// Hard link to the source; symlink if they are on different
+    /// filesystems
+    Hardlink,
+    /// The source rendered as a template, tracked like a copy (implied by a
+    /// `.tmpl` suffix)
+    Template,
+}
+
+impl Mode {
+    /// Is the destination a file written by dotr, rather than a link?
+    pub(crate) fn writes_content(self) -> bool {
+        matches!(self, Mode::Copy | Mode::Template)
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Mode::Symlink => "symlink",
+            Mode::Copy => "copy",
+            Mode::Hardlink => "hardlink",
+            Mode::Template => "template",
+        })
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "symlink" => Ok(Mode::Symlink),
+            "copy" => Ok(Mode::Copy),
+            "hardlink" => Ok(Mode::Hardlink),
+            "template" => Ok(Mode::Template),
+            _ => Err(format!(
+                "unknown mode `{s}`, expected `symlink`, `copy`, `hardlink` or `template`"
+            )),
+        }
+    }
+}
+
+/// Settings of a single file, under `[files."name"]`
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileConfig {
+    pub(crate) mode: Option<Mode>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DirConfig {
+    pub(crate) traverse: Option<Traverse>,
+    /// Entries of this directory to skip (gitignore syntax)
+    #[serde(default)]
+    pub(crate) ignore: Vec<String>,
+    /// Where to place this directory instead of its mirrored path; relative
+    /// to the destination base, or absolute
+    pub(crate) target: Option<PathBuf>,
+    /// Source names starting with this prefix are linked as hidden files,
+    /// e.g. `dot_bashrc` as `.bashrc` (root `.dotr` only)
+    pub(crate) dot_prefix: Option<String>,
+    /// How to install the files of this directory and its subdirectories
+    pub(crate) mode: Option<Mode>,
+    /// Per-file settings, by name
+    #[serde(default)]
+    pub(crate) files: BTreeMap<String, FileConfig>,
+    /// Only walk this directory on these hosts
+    #[serde(default)]
+    pub(crate) hosts: Vec<String>,
+    /// Only walk this directory with one of these profiles active
+    #[serde(default)]
+    pub(crate) profiles: Vec<String>,
+}
+
+/// A `.dotr`, `.dotrignore`, variables or state file that couldn't be read
+/// or parsed
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    /// 0-based line and column of the error, if known
+    pub line_col: Option<(usize, usize)>,
+    message: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(path: &Path, message: impl fmt::Display) -> Self {
+        Self {
+            path: path.to_owned(),
+            line_col: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn from_toml(path: &Path, content: &str, e: toml::de::Error) -> Self {
+        let line_col = e.span().map(|span| {
+            let before = &content[..span.start];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            (before.matches('\n').count(), span.start - line_start)
+        });
+        Self {
+            line_col,
+            ..Self::new(path, e.message())
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}", self.path.display())?;
+        if let Some((line, col)) = self.line_col {
+            write!(f, " (line {}, column {})", line + 1, col + 1)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::ConfigParse(e)
+    }
+}
+
+/// Read the `.dotr` of `dir`, if it has one
+pub(crate) fn read_dir_config(dir: &Path) -> Result<DirConfig, ConfigError> {
+    let config_path = dir.join(DOTR_CONFIG_FILE);
+    let content = match fs::read_to_string(&config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DirConfig::default()),
+        Err(e) => return Err(ConfigError::new(&config_path, e)),
+    };
+    toml::from_str(&content).map_err(|e| ConfigError::from_toml(&config_path, &content, e))
+}
+
+impl Dotr {
+    /// Validate every `.dotr` (and `.dotrignore`) file in the source tree,
+    /// without touching the destination
+    ///
+    /// Returns all the problems found, if any.
+    pub fn check(&self, src_base: &Path) -> Result<Vec<ConfigError>> {
+        info!(src = %src_base.display(), "Checking config files");
+
+        let mut errors = vec![];
+        let mut iter = WalkDir::new(src_base.canonicalize().at(src_base, "resolve")?)
+            .sort_by_file_name()
+            .into_iter();
+        while let Some(entry) = iter.next() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("Error walking: {}", e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+            if !should_traverse(&entry) {
+                iter.skip_current_dir();
+                continue;
+            }
+            let result = read_dir_config(entry.path())
+                .and_then(|config| dir_matcher(entry.path(), &config.ignore));
+            if let Err(e) = result {
+                warn!("{e}");
+                errors.push(e);
+            }
+        }
+
+        Ok(errors)
+    }
+}
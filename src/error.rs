@@ -0,0 +1,228 @@
+use std::path::{Path, PathBuf};
+use std::{error, fmt, io};
+
+use crate::{ConfigError, Failures};
+
+/// Result of a [`crate::Dotr`] operation
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Why a [`crate::Dotr`] operation failed, along with the paths involved
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The destination directory doesn't exist
+    DestinationMissing { path: PathBuf },
+    /// The destination exists, but is not a directory
+    DestinationNotDir { path: PathBuf },
+    /// No source directory was given
+    NoSource,
+    /// `dst` is in the way of `src`, and can't be moved aside
+    Conflict {
+        src: PathBuf,
+        dst: PathBuf,
+        kind: ConflictKind,
+    },
+    /// A `.dotr`, `.dotrignore`, variables or state file is invalid
+    ConfigParse(ConfigError),
+    /// An `--exclude` pattern is invalid
+    InvalidPattern { pattern: String, message: String },
+    /// The template at `path` can't be rendered
+    Template { path: PathBuf, message: String },
+    /// `path` can't be adopted
+    NotAdoptable { path: PathBuf, reason: &'static str },
+    /// There's no backup in `path`, the directory of backups
+    NoBackups { path: PathBuf },
+    /// The backup `path` doesn't exist
+    BackupMissing { path: PathBuf },
+    /// `op` (e.g. `remove`) failed on `path`
+    Io {
+        path: PathBuf,
+        op: &'static str,
+        source: io::Error,
+    },
+    /// Some entries failed (see [`crate::Dotr::set_keep_going`])
+    Failures(Failures),
+    /// A step failed, and every change made so far was undone, unless not
+    /// `complete` (see [`crate::Dotr::set_atomic`])
+    RolledBack { source: Box<Error>, complete: bool },
+}
+
+impl Error {
+    /// Name of the variant, e.g. `destination-missing`, for machine-readable
+    /// output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::DestinationMissing { .. } => "destination-missing",
+            Error::DestinationNotDir { .. } => "destination-not-dir",
+            Error::NoSource => "no-source",
+            Error::Conflict { .. } => "conflict",
+            Error::ConfigParse(_) => "config-parse",
+            Error::InvalidPattern { .. } => "invalid-pattern",
+            Error::Template { .. } => "template",
+            Error::NotAdoptable { .. } => "not-adoptable",
+            Error::NoBackups { .. } => "no-backups",
+            Error::BackupMissing { .. } => "backup-missing",
+            Error::Io { .. } => "io",
+            Error::Failures(_) => "failures",
+            Error::RolledBack { .. } => "rolled-back",
+        }
+    }
+
+    /// The path involved, if any (the destination of a conflict)
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::DestinationMissing { path }
+            | Error::DestinationNotDir { path }
+            | Error::Template { path, .. }
+            | Error::NotAdoptable { path, .. }
+            | Error::NoBackups { path }
+            | Error::BackupMissing { path }
+            | Error::Io { path, .. } => Some(path),
+            Error::Conflict { dst, .. } => Some(dst),
+            Error::ConfigParse(e) => Some(&e.path),
+            Error::RolledBack { source, .. } => source.path(),
+            Error::NoSource | Error::InvalidPattern { .. } | Error::Failures(_) => None,
+        }
+    }
+}
+
+/// Why a destination is in the way, either of an [`Error::Conflict`], or of
+/// an entry skipped as [`crate::Outcome::ConflictSkipped`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum ConflictKind {
+    /// It was to be backed up, but `backup` already exists
+    BackupExists { backup: PathBuf },
+    /// It was to be adopted, but the source already exists with different
+    /// content
+    SourceDiffers,
+    /// It's a real directory, which is never removed
+    DirectoryInTheWay,
+    /// One of its parents is not a directory
+    ParentNotDir,
+    /// It wasn't installed by dotr, e.g. a regular file, or a symlink
+    /// pointing elsewhere
+    NotOurs,
+    /// It was installed by dotr, but modified since
+    Modified,
+    /// It's outside of the destination directory, so it can't be backed up
+    OutsideDestination,
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictKind::BackupExists { backup } => {
+                write!(f, "backup {} already exists", backup.display())
+            }
+            ConflictKind::SourceDiffers => {
+                f.write_str("source already exists with different content")
+            }
+            ConflictKind::DirectoryInTheWay => f.write_str("real directory in the way"),
+            ConflictKind::ParentNotDir => f.write_str("a parent is not a directory"),
+            ConflictKind::NotOurs => f.write_str("not installed by dotr"),
+            ConflictKind::Modified => f.write_str("modified since installed"),
+            ConflictKind::OutsideDestination => f.write_str("outside of the destination"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DestinationMissing { path } => {
+                write!(f, "Destination {} doesn't exist", path.display())
+            }
+            Error::DestinationNotDir { path } => {
+                write!(f, "Destination {} is not a directory", path.display())
+            }
+            Error::NoSource => f.write_str("No source directory"),
+            Error::Conflict { src, dst, kind } => match kind {
+                ConflictKind::BackupExists { backup } => write!(
+                    f,
+                    "Can't back up {}: {} already exists",
+                    dst.display(),
+                    backup.display()
+                ),
+                ConflictKind::SourceDiffers => write!(
+                    f,
+                    "Can't adopt {}: source {} already exists with different content",
+                    dst.display(),
+                    src.display()
+                ),
+                kind => write!(f, "Conflict at {}: {kind}", dst.display()),
+            },
+            Error::ConfigParse(e) => e.fmt(f),
+            Error::InvalidPattern { pattern, message } => {
+                write!(f, "Invalid exclude pattern `{pattern}`: {message}")
+            }
+            Error::Template { path, message } => {
+                write!(f, "Template {}: {message}", path.display())
+            }
+            Error::NotAdoptable { path, reason } => {
+                write!(f, "Can't adopt {}: {reason}", path.display())
+            }
+            Error::NoBackups { path } => write!(f, "No backups in {}", path.display()),
+            Error::BackupMissing { path } => write!(f, "Backup {} doesn't exist", path.display()),
+            Error::Io { path, op, source } => {
+                write!(f, "Can't {op} {}: {source}", path.display())
+            }
+            Error::Failures(failures) => failures.fmt(f),
+            Error::RolledBack { source, complete } => {
+                let note = if *complete {
+                    "every change was rolled back"
+                } else {
+                    "rolling back failed too, see the log"
+                };
+                write!(f, "{source} ({note})")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::RolledBack { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// For callers sticking to [`io::Result`]; the original [`Error`] can be
+/// recovered with [`io::Error::downcast`]
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match &e {
+            Error::DestinationMissing { .. } | Error::BackupMissing { .. } => {
+                io::ErrorKind::NotFound
+            }
+            Error::DestinationNotDir { .. } => io::ErrorKind::NotADirectory,
+            Error::Conflict { .. } => io::ErrorKind::AlreadyExists,
+            Error::ConfigParse(_) | Error::InvalidPattern { .. } | Error::Template { .. } => {
+                io::ErrorKind::InvalidData
+            }
+            Error::Io { source, .. } => source.kind(),
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Attaching the path involved to IO errors
+pub(crate) trait IoContext<T> {
+    /// `self`, with an error from `op` (e.g. `remove`) on `path`
+    fn at(self, path: &Path, op: &'static str) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at(self, path: &Path, op: &'static str) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            path: path.to_owned(),
+            op,
+            source,
+        })
+    }
+}
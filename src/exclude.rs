@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tracing::debug;
+
+use crate::config::{ConfigError, DOTR_CONFIG_FILE};
+use crate::{Error, Result};
+
+/// Per-directory exclusion file, in gitignore syntax
+pub const DOTR_IGNORE_FILE: &str = ".dotrignore";
+
+/// `.dotrignore` (and `.dotr` `ignore`) matchers of the directories enclosing the entry currently
+/// being walked, plus the `--exclude` patterns at the bottom
+pub(crate) struct Excludes {
+    /// `(directory, matcher)`, outermost first
+    stack: Vec<(PathBuf, Gitignore)>,
+}
+
+impl Excludes {
+    pub(crate) fn new(src_base: &Path, patterns: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(src_base);
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| Error::InvalidPattern {
+                    pattern: pattern.clone(),
+                    message: e.to_string(),
+                })?;
+        }
+        let matcher = builder.build().map_err(|e| Error::InvalidPattern {
+            pattern: patterns.join(" "),
+            message: e.to_string(),
+        })?;
+        Ok(Self {
+            stack: vec![(src_base.to_owned(), matcher)],
+        })
+    }
+
+    /// Is `path` excluded?
+    ///
+    /// Must be called for every entry in walk order, so matchers of
+    /// directories that were left can be dropped.
+    pub(crate) fn is_excluded(&mut self, path: &Path, is_dir: bool) -> bool {
+        while self
+            .stack
+            .last()
+            .is_some_and(|(dir, _)| dir.as_path() == path || !path.starts_with(dir))
+        {
+            self.stack.pop();
+        }
+
+        // Deeper files take precedence, so they can re-include (`!pattern`)
+        // what an outer one excluded
+        for (dir, matcher) in self.stack.iter().rev() {
+            match matcher.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) => {
+                    debug!(path = %path.display(), dir = %dir.display(), pattern = %glob.original(), "Excluded");
+                    return true;
+                }
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// Start applying the `.dotrignore` in `dir`, if any, and the `.dotr`
+    /// `ignore` patterns to its content
+    pub(crate) fn enter_dir(&mut self, dir: &Path, patterns: &[String]) -> Result<()> {
+        if let Some(matcher) = dir_matcher(dir, patterns)? {
+            self.stack.push((dir.to_owned(), matcher));
+        }
+        Ok(())
+    }
+}
+
+/// Matcher for the `.dotrignore` of `dir` and its `.dotr` `ignore`
+/// `patterns`, if it has any
+pub(crate) fn dir_matcher(
+    dir: &Path,
+    patterns: &[String],
+) -> Result<Option<Gitignore>, ConfigError> {
+    let ignore_path = dir.join(DOTR_IGNORE_FILE);
+    if !ignore_path.is_file() && patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if ignore_path.is_file()
+        && let Some(e) = builder.add(&ignore_path)
+    {
+        return Err(ConfigError::new(&ignore_path, e));
+    }
+    let config_path = dir.join(DOTR_CONFIG_FILE);
+    for pattern in patterns {
+        builder
+            .add_line(Some(config_path.clone()), pattern)
+            .map_err(|e| ConfigError::new(&config_path, e))?;
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| ConfigError::new(dir, e))
+}
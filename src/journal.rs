@@ -0,0 +1,173 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use tracing::{debug, warn};
+
+use crate::error::IoContext;
+use crate::plan::sibling_path;
+use crate::{Action, Result};
+
+/// How to revert a single change
+#[derive(Debug)]
+enum Undo {
+    /// Remove the file or symlink created at `path`
+    Remove(PathBuf),
+    /// Remove the directory created at `path`
+    RemoveDir(PathBuf),
+    /// Recreate the (empty) directory removed from `path`
+    CreateDir(PathBuf),
+    /// Move `from` back to `to`, where it was moved from
+    Move { from: PathBuf, to: PathBuf },
+    /// Move `stash` back to `path`, which it was stashed instead of being
+    /// removed from
+    Unstash { stash: PathBuf, path: PathBuf },
+}
+
+impl Undo {
+    fn apply(&self) -> io::Result<()> {
+        match self {
+            Undo::Remove(path) => fs::remove_file(path),
+            Undo::RemoveDir(path) => fs::remove_dir(path),
+            Undo::CreateDir(path) => fs::create_dir(path),
+            Undo::Move { from, to } => {
+                fs::create_dir_all(to.parent().unwrap())?;
+                move_path(from, to)
+            }
+            Undo::Unstash { stash, path } => fs::rename(stash, path),
+        }
+    }
+}
+
+/// Every change made by an atomic [`crate::Plan::apply`], so they can all be
+/// undone if a step fails
+///
+/// Nothing is removed until [`Journal::commit`]: files are stashed next to
+/// where they were instead.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    /// In the order made
+    undo: Vec<Undo>,
+}
+
+impl Journal {
+    /// Apply `action`, keeping track of how to undo it
+    pub(crate) fn apply(&mut self, action: &Action) -> Result<()> {
+        match action {
+            Action::CreateDirAll { path } => {
+                let mut missing: Vec<_> = path
+                    .ancestors()
+                    .take_while(|dir| dir.symlink_metadata().is_err())
+                    .map(Path::to_owned)
+                    .collect();
+                action.apply()?;
+                // Outermost first, so they're removed innermost first
+                missing.reverse();
+                self.undo.extend(missing.into_iter().map(Undo::RemoveDir));
+            }
+            Action::RemoveFile { path } => self.stash(path)?,
+            Action::RemoveDir { path } => {
+                action.apply()?;
+                self.undo.push(Undo::CreateDir(path.clone()));
+            }
+            Action::ReplaceSymlink { target, path } => {
+                self.stash(path)?;
+                std::os::unix::fs::symlink(target, path).at(path, "create symlink")?;
+                self.undo.push(Undo::Remove(path.clone()));
+            }
+            Action::CreateSymlink { path, .. } | Action::CreateHardlink { path, .. } => {
+                action.apply()?;
+                self.undo.push(Undo::Remove(path.clone()));
+            }
+            // These replace whatever is at `path`
+            Action::CopyFile { path, .. } | Action::RenderTemplate { path, .. } => {
+                if path.symlink_metadata().is_ok() {
+                    self.stash(path)?;
+                }
+                action.apply()?;
+                self.undo.push(Undo::Remove(path.clone()));
+            }
+            Action::MoveToBackup { path, backup } => {
+                action.apply()?;
+                self.undo.push(Undo::Move {
+                    from: backup.clone(),
+                    to: path.clone(),
+                });
+            }
+            Action::RestoreBackup { backup, path } => {
+                if path
+                    .symlink_metadata()
+                    .is_ok_and(|m| m.file_type().is_symlink())
+                {
+                    self.stash(path)?;
+                }
+                action.apply()?;
+                self.undo.push(Undo::Move {
+                    from: path.clone(),
+                    to: backup.clone(),
+                });
+            }
+            Action::Adopt { path, src } => {
+                // Replaces the source, if it exists
+                if src.symlink_metadata().is_ok() {
+                    self.stash(src)?;
+                }
+                action.apply()?;
+                self.undo.push(Undo::Move {
+                    from: src.clone(),
+                    to: path.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Move `path` aside, instead of removing it
+    fn stash(&mut self, path: &Path) -> Result<()> {
+        let stash = sibling_path(path, ".dotr-stash")?;
+        debug!(path = %path.display(), stash = %stash.display(), "Stashing");
+        fs::rename(path, &stash).at(path, "stash")?;
+        self.undo.push(Undo::Unstash {
+            stash,
+            path: path.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Remove everything stashed, making the changes final
+    pub(crate) fn commit(self) {
+        for undo in self.undo {
+            if let Undo::Unstash { stash, .. } = undo
+                && let Err(e) = fs::remove_file(&stash)
+            {
+                warn!(stash = %stash.display(), error = %e, "Can't remove stashed file");
+            }
+        }
+    }
+
+    /// Undo every change, latest first
+    ///
+    /// Carries on past changes that can't be undone; returns whether all
+    /// of them were.
+    pub(crate) fn rollback(self) -> bool {
+        let mut complete = true;
+        for undo in self.undo.into_iter().rev() {
+            debug!(?undo, "Rolling back");
+            if let Err(e) = undo.apply() {
+                warn!(?undo, error = %e, "Can't roll back");
+                complete = false;
+            }
+        }
+        complete
+    }
+}
+
+/// Rename `from` to `to`, copying it if they are on different filesystems
+pub(crate) fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        res => res,
+    }
+}
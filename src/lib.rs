@@ -1,34 +1,523 @@
-use std::ffi::OsStr;
-use std::path::Path;
-use std::{fs, io};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::{fmt, io};
 
+pub use config::{ConfigError, Mode};
+use config::{DOTR_CONFIG_FILE, FileConfig, Traverse, read_dir_config};
+use error::IoContext;
+pub use error::{ConflictKind, Error, Result};
+use exclude::{DOTR_IGNORE_FILE, Excludes};
+use plan::OnError;
+pub use plan::{Action, Plan, PlanEntry, Step};
+pub use resolve::{Conflict, Resolution, Resolver};
+use state::{Record, State, content_hash};
+use template::{TEMPLATE_SUFFIX, Vars};
 use tracing::{debug, info, trace, warn};
+use variant::Selector;
 use walkdir::WalkDir;
 
-const DOTR_CONFIG_FILE: &str = ".dotr";
+mod adopt;
+mod backup;
+mod config;
+mod error;
+mod exclude;
+mod journal;
+mod plan;
+mod prune;
+mod resolve;
+mod state;
+mod template;
+mod variant;
 
-#[derive(serde::Deserialize, PartialEq, Eq, Debug)]
-#[serde(rename_all = "lowercase")]
-enum Traverse {
-    Link,
+enum WalkItem {
+    /// A file or symlink to be handled individually
+    Entry {
+        src: PathBuf,
+        dst: PathBuf,
+        mode: Mode,
+    },
+    /// A directory to be linked as a whole (`traverse = "link"`)
+    LinkedDir { src: PathBuf, dst: PathBuf },
+    /// A directory that can't be walked, skipped when keeping going (see
+    /// [`Dotr::set_keep_going`])
+    Failed {
+        src: PathBuf,
+        dst: PathBuf,
+        error: Error,
+    },
 }
 
-#[derive(serde::Deserialize, Default)]
-struct DirConfig {
-    traverse: Option<Traverse>,
+impl WalkItem {
+    fn src(&self) -> &Path {
+        match self {
+            WalkItem::Entry { src, .. }
+            | WalkItem::LinkedDir { src, .. }
+            | WalkItem::Failed { src, .. } => src,
+        }
+    }
+
+    fn dst(&self) -> &Path {
+        match self {
+            WalkItem::Entry { dst, .. }
+            | WalkItem::LinkedDir { dst, .. }
+            | WalkItem::Failed { dst, .. } => dst,
+        }
+    }
+}
+
+/// Everything shared by all the entries planned in a single run
+struct Ctx {
+    src_base: PathBuf,
+    /// `src_base` of every layer of the run, in order
+    layers: Vec<PathBuf>,
+    dst_base: PathBuf,
+    /// Root `.dotr` `dot_prefix`
+    dot_prefix: Option<String>,
+    /// `None` if disabled, or there's no state directory to keep it in
+    state_file: Option<PathBuf>,
+    state: State,
+    /// Id of the backup to move destinations into, when backing up
+    backup_id: String,
+    vars: Vars,
+    selector: Selector,
+}
+
+impl Ctx {
+    fn new(dotr: &Dotr, src_base: &Path, dst_base: &Path) -> Result<Self> {
+        let src_base = src_base.canonicalize().at(src_base, "resolve")?;
+        let dst_base = resolve_dst_base(dst_base)?;
+        let root_config = read_dir_config(&src_base)?;
+        let state_file = dotr
+            .state_dir
+            .as_deref()
+            .map(|dir| state::state_file(dir, &dst_base));
+        let state = match &state_file {
+            Some(path) => State::load(path)?,
+            None => State::default(),
+        };
+        let ctx = Self {
+            layers: vec![src_base.clone()],
+            src_base,
+            dot_prefix: root_config.dot_prefix,
+            state_file,
+            state,
+            backup_id: backup::new_backup_id(&dst_base),
+            dst_base,
+            vars: Vars::new(dotr.vars_file.as_deref())?,
+            selector: Selector {
+                hostname: template::hostname(),
+                profiles: dotr.profiles.clone(),
+            },
+        };
+
+        assert!(ctx.dst_base.is_absolute());
+        assert!(ctx.src_base.is_absolute());
+
+        Ok(ctx)
+    }
+
+    /// Is `record` for a source of this run that gave way to the one of
+    /// `src_base`, so what it installed can be replaced or removed?
+    ///
+    /// That's if the source is gone (e.g. renamed), or shadowed by this
+    /// later layer. What other source directories installed, or other
+    /// existing sources of the same layer, are not to be touched.
+    fn superseded(&self, record: &Record) -> bool {
+        let layer = |path: &Path| self.layers.iter().position(|base| path.starts_with(base));
+        match (layer(&record.src), layer(&self.src_base)) {
+            (Some(recorded), Some(current)) => recorded < current || is_gone(&record.src),
+            _ => false,
+        }
+    }
+
+    /// Destination path (relative) of the source path `src_rel`, with the
+    /// `dot_prefix` translated in every component
+    fn dst_rel(&self, src_rel: &Path) -> PathBuf {
+        let Some(prefix) = &self.dot_prefix else {
+            return src_rel.to_owned();
+        };
+        src_rel
+            .components()
+            .map(|c| {
+                let name = c.as_os_str();
+                match name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(prefix.as_str()))
+                {
+                    Some(rest) if !rest.is_empty() => OsString::from(format!(".{rest}")),
+                    _ => name.to_owned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Action installing `src` at `dst` per `mode`
+    fn install_action(&self, src: &Path, dst: &Path, target: &Path, mode: Mode) -> Result<Action> {
+        Ok(match mode {
+            Mode::Symlink => Action::CreateSymlink {
+                target: target.to_owned(),
+                path: dst.to_owned(),
+            },
+            Mode::Copy => Action::CopyFile {
+                src: src.to_owned(),
+                path: dst.to_owned(),
+            },
+            Mode::Hardlink => Action::CreateHardlink {
+                src: src.to_owned(),
+                path: dst.to_owned(),
+            },
+            Mode::Template => Action::RenderTemplate {
+                src: src.to_owned(),
+                path: dst.to_owned(),
+                content: self.vars.render(src)?,
+            },
+        })
+    }
+
+    /// Entry replacing `dst`, known to be ours, with a fresh install of
+    /// `src`
+    fn plan_reinstall(
+        &self,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+        reason: String,
+    ) -> Result<PlanEntry> {
+        let entry = PlanEntry::new(src, dst, Outcome::Updated);
+        let install = self.install_action(src, dst, target, mode)?;
+        Ok(if mode.writes_content() {
+            // Written files replace the destination on their own
+            entry.step(install, reason)
+        } else {
+            entry
+                .step(
+                    Action::RemoveFile {
+                        path: dst.to_owned(),
+                    },
+                    reason,
+                )
+                .step(install, "destination removed")
+        })
+    }
+
+    /// Hash of what `src` should be written as, per `mode` (its content,
+    /// unless a template)
+    fn expected_hash(&self, src: &Path, mode: Mode) -> Result<String> {
+        match mode {
+            Mode::Template => Ok(state::hash(self.vars.render(src)?.as_bytes())),
+            _ => content_hash(src),
+        }
+    }
+
+    /// Inverse of [`Ctx::dst_rel`]
+    fn src_rel(&self, dst_rel: &Path) -> PathBuf {
+        let Some(prefix) = &self.dot_prefix else {
+            return dst_rel.to_owned();
+        };
+        dst_rel
+            .components()
+            .map(|c| {
+                let name = c.as_os_str();
+                match name.to_str().and_then(|name| name.strip_prefix('.')) {
+                    Some(rest) if !rest.is_empty() => OsString::from(format!("{prefix}{rest}")),
+                    _ => name.to_owned(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// What kind of source a managed destination corresponds to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    /// Regular file, linked individually
+    File,
+    /// Regular file, copied individually (`mode = "copy"`)
+    Copy,
+    /// Regular file, hard linked individually (`mode = "hardlink"`)
+    Hardlink,
+    /// Template, rendered individually (`.tmpl` or `mode = "template"`)
+    Template,
+    /// Symlink, duplicated as-is
+    Symlink,
+    /// Whole directory, linked per `.dotr` `traverse = "link"`
+    DirLink,
+}
+
+impl EntryKind {
+    /// Kind of a regular file installed per `mode`
+    pub(crate) fn of_mode(mode: Mode) -> Self {
+        match mode {
+            Mode::Symlink => EntryKind::File,
+            Mode::Copy => EntryKind::Copy,
+            Mode::Hardlink => EntryKind::Hardlink,
+            Mode::Template => EntryKind::Template,
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            EntryKind::File => "file",
+            EntryKind::Copy => "copy",
+            EntryKind::Hardlink => "hardlink",
+            EntryKind::Template => "template",
+            EntryKind::Symlink => "symlink",
+            EntryKind::DirLink => "dir-link",
+        })
+    }
+}
+
+/// State of a single destination, as reported by [`Dotr::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryState {
+    /// Destination is a symlink pointing where it should
+    Linked,
+    /// Destination doesn't exist
+    Missing,
+    /// Destination exists and is not a symlink
+    Conflict,
+    /// Destination is a symlink pointing to the source through another path,
+    /// e.g. a symlinked source directory
+    Equivalent(PathBuf),
+    /// Destination is a symlink pointing somewhere else
+    PointsElsewhere(PathBuf),
+    /// Destination is a copy (or rendering) of the source, up to date
+    Copied,
+    /// Destination is a copy of the source, but one of them changed since
+    Drifted,
+    /// Destination is a hard link to the source
+    Hardlinked,
+    /// Destination was installed from a source that's gone (see
+    /// [`Dotr::prune`])
+    Stale,
+}
+
+impl fmt::Display for EntryState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            EntryState::Linked => "linked",
+            EntryState::Missing => "missing",
+            EntryState::Conflict => "conflict",
+            EntryState::Equivalent(_) => "equivalent",
+            EntryState::PointsElsewhere(_) => "elsewhere",
+            EntryState::Copied => "copied",
+            EntryState::Drifted => "drifted",
+            EntryState::Hardlinked => "hardlinked",
+            EntryState::Stale => "stale",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub kind: EntryKind,
+    pub state: EntryState,
+}
+
+impl StatusEntry {
+    pub fn is_in_sync(&self) -> bool {
+        match self.kind {
+            EntryKind::Copy | EntryKind::Template => self.state == EntryState::Copied,
+            EntryKind::Hardlink => self.state == EntryState::Hardlinked,
+            _ => matches!(self.state, EntryState::Linked | EntryState::Equivalent(_)),
+        }
+    }
+}
+
+/// What happened (or, in dry-run mode, would happen) to a single entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// Link was created
+    Created,
+    /// Destination was ours, and was reinstalled (e.g. source changed since
+    /// copied, or mode changed)
+    Updated,
+    /// Destination already pointed to the source
+    AlreadyCorrect,
+    /// Destination was in the way and left untouched
+    ConflictSkipped,
+    /// Destination was in the way and removed (`force`)
+    ForceReplaced,
+    /// Destination was in the way and moved to a backup
+    BackedUp,
+    /// Destination was restored from a backup
+    Restored,
+    /// Destination was moved into the source
+    Adopted,
+    /// Destination was unlinked
+    Removed,
+    /// Nothing to do for this entry
+    Skipped,
+    /// Entry of a source directory overridden by a later one
+    Shadowed,
+    /// Entry couldn't be planned or applied (see [`Dotr::set_keep_going`])
+    Failed,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 12] = [
+        Outcome::Created,
+        Outcome::Updated,
+        Outcome::AlreadyCorrect,
+        Outcome::ConflictSkipped,
+        Outcome::ForceReplaced,
+        Outcome::BackedUp,
+        Outcome::Restored,
+        Outcome::Adopted,
+        Outcome::Removed,
+        Outcome::Skipped,
+        Outcome::Shadowed,
+        Outcome::Failed,
+    ];
 }
 
-fn read_dir_config(dir: &Path) -> DirConfig {
-    let config_path = dir.join(DOTR_CONFIG_FILE);
-    fs::read_to_string(&config_path)
-        .ok()
-        .and_then(|content| toml::from_str(&content).ok())
-        .unwrap_or_default()
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::AlreadyCorrect => "already correct",
+            Outcome::ConflictSkipped => "conflict skipped",
+            Outcome::ForceReplaced => "force replaced",
+            Outcome::BackedUp => "backed up",
+            Outcome::Restored => "restored",
+            Outcome::Adopted => "adopted",
+            Outcome::Removed => "removed",
+            Outcome::Skipped => "skipped",
+            Outcome::Shadowed => "shadowed",
+            Outcome::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportEntry {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub kind: Option<EntryKind>,
+    pub outcome: Outcome,
+    /// Why the destination is in the way, if [`Outcome::ConflictSkipped`]
+    pub conflict: Option<ConflictKind>,
+    /// Cause of an [`Outcome::Failed`], or why it was [`Outcome::Skipped`]
+    pub error: Option<String>,
+}
+
+impl ReportEntry {
+    fn new(src: &Path, dst: &Path, outcome: Outcome) -> Self {
+        Self {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            kind: None,
+            outcome,
+            conflict: None,
+            error: None,
+        }
+    }
+}
+
+/// Result of [`Dotr::link`] or [`Dotr::unlink`]: one entry per handled path
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub entries: Vec<ReportEntry>,
+}
+
+impl Report {
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.entries.iter().filter(|e| e.outcome == outcome).count()
+    }
+
+    /// The report, or [`Error::Failures`] if any entry failed
+    fn into_result(self) -> Result<Self> {
+        if self.count(Outcome::Failed) == 0 {
+            Ok(self)
+        } else {
+            Err(Error::Failures(Failures { report: self }))
+        }
+    }
+}
+
+/// One-line summary, e.g. `2 created, 1 conflict skipped`
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for outcome in Outcome::ALL {
+            let count = self.count(outcome);
+            if count == 0 {
+                continue;
+            }
+            if !first {
+                f.write_str(", ")?;
+            }
+            write!(f, "{count} {outcome}")?;
+            first = false;
+        }
+        if first {
+            f.write_str("nothing to do")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error of a [`Dotr::set_keep_going`] run in which some entries failed,
+/// with the outcome of every entry (see [`Error::Failures`])
+#[derive(Debug)]
+pub struct Failures {
+    pub report: Report,
+}
+
+/// Every failed path and its cause, one per line
+impl fmt::Display for Failures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} entries failed",
+            self.report.count(Outcome::Failed),
+            self.report.entries.len()
+        )?;
+        for entry in &self.report.entries {
+            if let Some(error) = &entry.error {
+                write!(f, "\n{}: {error}", entry.dst.display())?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct Dotr {
     dry_run: bool,
     force: bool,
+    /// Extra gitignore-style patterns of source paths to skip
+    excludes: Vec<String>,
+    /// Move conflicting destinations into a backup
+    backup: bool,
+    /// How to install files without a `.dotr` `mode`
+    mode: Mode,
+    /// Where to record what was installed, if anywhere
+    state_dir: Option<PathBuf>,
+    /// File with user-defined template variables
+    vars_file: Option<PathBuf>,
+    /// Active profiles, for selecting variants
+    profiles: Vec<String>,
+    /// What to do when an entry fails
+    on_error: OnError,
+    /// Create symlinks with relative targets
+    relative: bool,
+    /// Rewrite symlinks pointing to their source through another path
+    rewrite_equivalent: bool,
+    /// Back up real directories in the way too
+    force_dirs: bool,
+    /// Asked about conflicts that would otherwise be skipped
+    resolver: Option<Mutex<Box<dyn Resolver>>>,
 }
 
 impl Dotr {
@@ -36,6 +525,170 @@ impl Dotr {
         Dotr {
             dry_run: false,
             force: false,
+            excludes: vec![],
+            backup: false,
+            mode: Mode::Symlink,
+            state_dir: None,
+            vars_file: None,
+            profiles: vec![],
+            on_error: OnError::Stop,
+            relative: false,
+            rewrite_equivalent: false,
+            force_dirs: false,
+            resolver: None,
+        }
+    }
+
+    /// Activate `profile`, selecting `##profile.<profile>` variants and
+    /// directories with it in their `.dotr` `profiles`
+    pub fn add_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profiles.push(profile.into());
+        self
+    }
+
+    /// Make the variables defined in `path` (a TOML table of strings)
+    /// available to templates, along with the built-in ones
+    pub fn set_vars_file(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            vars_file: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Install files with `mode`, unless their `.dotr` says otherwise
+    pub fn set_mode(self, mode: Mode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Keep the record of what was installed in `dir`
+    ///
+    /// Nothing is recorded by default (see [`Dotr::set_no_state`]).
+    pub fn set_state_dir(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            state_dir: Some(dir.into()),
+            ..self
+        }
+    }
+
+    /// Keep the record of what was installed in `$XDG_STATE_HOME/dotr`
+    /// (`~/.local/state/dotr`), as the `dotr` command does
+    ///
+    /// Nothing is recorded if neither `$XDG_STATE_HOME` nor `$HOME` is set.
+    pub fn set_default_state(self) -> Self {
+        Self {
+            state_dir: state::default_state_dir(),
+            ..self
+        }
+    }
+
+    /// Don't read or record what was installed (the default)
+    ///
+    /// Destinations are then only recognized by looking at them, so copies
+    /// (and templates) can't be installed, and destinations whose source
+    /// is gone can only be found by [`Dotr::prune`] if they're symlinks.
+    pub fn set_no_state(self) -> Self {
+        Self {
+            state_dir: None,
+            ..self
+        }
+    }
+
+    /// Skip source paths matching `pattern` (gitignore syntax, relative to
+    /// the source base), in addition to `.dotrignore` files
+    pub fn add_exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    /// Move conflicting destinations into a new backup (see
+    /// [`Dotr::restore`]) instead of skipping or deleting them
+    pub fn set_backup(self) -> Self {
+        Self {
+            backup: true,
+            ..self
+        }
+    }
+
+    /// In backup mode (see [`Dotr::set_backup`]), move real directories in
+    /// the way into the backup as well, rather than skipping them
+    ///
+    /// Real directories are never removed, even with [`Dotr::set_force`].
+    pub fn set_force_dirs(self) -> Self {
+        Self {
+            force_dirs: true,
+            ..self
+        }
+    }
+
+    /// Don't stop at the first entry that can't be planned or applied, but
+    /// handle all the others, then fail with [`Failures`]
+    pub fn set_keep_going(self) -> Self {
+        Self {
+            on_error: OnError::KeepGoing,
+            ..self
+        }
+    }
+
+    /// Undo every change made so far if a step fails to apply, leaving the
+    /// destination as it was before the run
+    ///
+    /// Nothing is deleted until all the steps were applied: what's to be
+    /// removed or replaced is stashed next to it (as `<name>.dotr-stash`,
+    /// numbered if taken) in the meantime. Exclusive with [`Dotr::set_keep_going`]: whichever
+    /// is set last applies.
+    pub fn set_atomic(self) -> Self {
+        Self {
+            on_error: OnError::Rollback,
+            ..self
+        }
+    }
+
+    /// Empty plan to be applied per the settings
+    fn new_plan(&self, state_file: Option<PathBuf>) -> Plan {
+        Plan::new(state_file, self.on_error)
+    }
+
+    /// `entry`, or when keeping going, an [`Outcome::Failed`] one if it's
+    /// an error
+    fn or_failed(&self, src: &Path, dst: &Path, entry: Result<PlanEntry>) -> Result<PlanEntry> {
+        match entry {
+            Err(e) if self.on_error == OnError::KeepGoing => {
+                warn!(src = %src.display(), dst = %dst.display(), error = %e, "Failed, carrying on");
+                Ok(PlanEntry::failed(src, dst, &e))
+            }
+            entry => entry,
+        }
+    }
+
+    /// Point symlinks to their source with a path relative to their own
+    /// directory, rather than an absolute one
+    ///
+    /// Either form of a symlink is recognized as pointing to its source
+    /// regardless.
+    pub fn set_relative(self) -> Self {
+        Self {
+            relative: true,
+            ..self
+        }
+    }
+
+    /// Rewrite symlinks pointing to their source through another path (see
+    /// [`EntryState::Equivalent`]) to what dotr would create, rather than
+    /// leaving them be
+    pub fn set_rewrite_equivalent(self) -> Self {
+        Self {
+            rewrite_equivalent: true,
+            ..self
+        }
+    }
+
+    /// What a symlink at `dst` pointing to `src` is to contain
+    fn symlink_target(&self, src: &Path, dst: &Path) -> PathBuf {
+        if self.relative {
+            // From where the link really is, as `..` goes up from there
+            relative_path(src, &real_path(dst.parent().unwrap()))
+        } else {
+            src.to_owned()
         }
     }
 
@@ -53,390 +706,990 @@ impl Dotr {
         }
     }
 
-    fn link_dir(&self, src: &Path, src_base: &Path, dst_base: &Path) -> io::Result<()> {
-        let src_rel = src.strip_prefix(src_base).unwrap();
-        let dst = dst_base.join(src_rel);
-
-        if dst.exists() || dst.symlink_metadata().is_ok() {
-            if self.force {
-                if dst
-                    .symlink_metadata()
-                    .map(|m| m.file_type().is_dir())
-                    .unwrap_or(false)
-                    && !dst
-                        .symlink_metadata()
-                        .map(|m| m.file_type().is_symlink())
-                        .unwrap_or(false)
-                {
-                    return Err(io::Error::other(format!(
-                        "Can't safely remove {} as it's a real directory",
-                        dst.display()
-                    )));
-                }
-                if !self.dry_run {
-                    debug!(src = %src.display(), dst = %dst.display(), "Force removing destination for directory link");
-                    fs::remove_file(&dst)?;
-                }
-            } else {
-                if dst
-                    .symlink_metadata()
-                    .map(|m| m.file_type().is_symlink())
-                    .unwrap_or(false)
-                {
-                    let dst_link = dst.read_link()?;
-                    if dst_link == src {
-                        debug!(src = %src.display(), dst = %dst.display(), "Directory symlink already correct");
-                        return Ok(());
-                    }
+    /// Plan linking `src` (either a file or a directory linked as a whole)
+    /// to `dst`, as a symlink pointing to `target`, a copy or a hard link,
+    /// per `mode`
+    fn plan_link_to(
+        &self,
+        ctx: &Ctx,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+    ) -> Result<PlanEntry> {
+        let dst_metadata = match dst.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotADirectory => {
+                warn!(src = %src.display(), dst = %dst.display(), "A parent of the destination is not a directory");
+                return Ok(PlanEntry::conflict(src, dst, ConflictKind::ParentNotDir));
+            }
+            Err(_) => {
+                let mut entry = PlanEntry::new(src, dst, Outcome::Created);
+                let parent = dst.parent().unwrap();
+                if parent.symlink_metadata().is_err() {
+                    entry = entry.step(
+                        Action::CreateDirAll {
+                            path: parent.to_owned(),
+                        },
+                        "parent directory missing",
+                    );
                 }
-                warn!(src = %src.display(), dst = %dst.display(), "Destination already exists");
-                return Ok(());
+                return Ok(entry.step(
+                    ctx.install_action(src, dst, target, mode)?,
+                    "destination missing",
+                ));
+            }
+        };
+        let dst_type = dst_metadata.file_type();
+
+        if self.backup {
+            if dst_type.is_dir() && !self.force_dirs {
+                warn!(src = %src.display(), dst = %dst.display(), "Destination is a real directory, refusing to back it up");
+                return Ok(PlanEntry::conflict(
+                    src,
+                    dst,
+                    ConflictKind::DirectoryInTheWay,
+                ));
             }
-        } else if !self.dry_run {
-            fs::create_dir_all(dst.parent().unwrap())?;
+            if let Some(entry) = self.plan_installed(ctx, src, dst, target, mode)? {
+                return Ok(entry);
+            }
+            return self.plan_backup(ctx, src, dst, target, mode);
         }
 
-        if !self.dry_run {
-            trace!(src = %src.display(), dst = %dst.display(), "Creating symlink to directory");
-            std::os::unix::fs::symlink(src, &dst)?;
+        if self.force {
+            if dst_type.is_dir() {
+                warn!(src = %src.display(), dst = %dst.display(), "Destination is a real directory, refusing to remove it");
+                return Ok(PlanEntry::conflict(
+                    src,
+                    dst,
+                    ConflictKind::DirectoryInTheWay,
+                ));
+            }
+            return self.plan_force_replace(ctx, src, dst, target, mode);
         }
-        Ok(())
-    }
 
-    fn unlink_dir(&self, src: &Path, src_base: &Path, dst_base: &Path) -> io::Result<()> {
-        let src_rel = src.strip_prefix(src_base).unwrap();
-        let dst = dst_base.join(src_rel);
+        if let Some(entry) = self.plan_installed(ctx, src, dst, target, mode)? {
+            return Ok(entry);
+        }
+        if let Some(resolver) = &self.resolver
+            && !dst_type.is_dir()
+        {
+            return self.plan_resolved(ctx, resolver, src, dst, target, mode);
+        }
+        if dst_type.is_symlink() {
+            let dst_link = dst.read_link().at(dst, "read link")?;
+            warn!(src = %src.display(), dst = %dst.display(), dst_dst = %dst_link.display(), "Destination already exists and points elsewhere");
+        } else if ctx.state.copy_of(dst, src).is_some() {
+            warn!(src = %src.display(), dst = %dst.display(), "Destination is a copy modified since it was installed");
+        } else {
+            warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is not a symlink");
+        }
+        Ok(PlanEntry::conflict(src, dst, conflict_kind(ctx, src, dst)?))
+    }
 
-        if dst.symlink_metadata().is_ok() {
-            let meta = dst.symlink_metadata()?;
-            if meta.file_type().is_symlink() {
-                let dst_link = dst.read_link()?;
-                if dst_link == src {
-                    if !self.dry_run {
-                        debug!(src = %src.display(), dst = %dst.display(), "Removing directory symlink");
-                        fs::remove_file(&dst)?;
-                    }
-                } else if self.force {
-                    if !self.dry_run {
-                        debug!(src = %src.display(), dst = %dst.display(), "Force removing directory symlink");
-                        fs::remove_file(&dst)?;
-                    }
+    /// Plan moving the existing `dst` into the backup, then linking `src`
+    /// to it
+    fn plan_backup(
+        &self,
+        ctx: &Ctx,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+    ) -> Result<PlanEntry> {
+        let Some(backup) = backup::backup_path(&ctx.dst_base, &ctx.backup_id, dst) else {
+            warn!(src = %src.display(), dst = %dst.display(), "Destination is outside of the destination base, refusing to back it up");
+            return Ok(PlanEntry::conflict(
+                src,
+                dst,
+                ConflictKind::OutsideDestination,
+            ));
+        };
+        if backup.symlink_metadata().is_ok() {
+            return Err(Error::Conflict {
+                src: src.to_owned(),
+                dst: dst.to_owned(),
+                kind: ConflictKind::BackupExists { backup },
+            });
+        }
+        debug!(src = %src.display(), dst = %dst.display(), backup = %backup.display(), "Backing up destination");
+        let is_dir = dst.symlink_metadata().is_ok_and(|meta| meta.is_dir());
+        Ok(PlanEntry::new(src, dst, Outcome::BackedUp)
+            .step(
+                Action::MoveToBackup {
+                    path: dst.to_owned(),
+                    backup,
+                },
+                if is_dir {
+                    "destination is a real directory"
                 } else {
-                    warn!(src = %src.display(), dst = %dst.display(), "Directory symlink points elsewhere");
+                    "destination already exists"
+                },
+            )
+            .step(
+                ctx.install_action(src, dst, target, mode)?,
+                "destination moved to backup",
+            ))
+    }
+
+    /// Plan removing the existing `dst` (not a real directory), then linking
+    /// `src` to it
+    fn plan_force_replace(
+        &self,
+        ctx: &Ctx,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+    ) -> Result<PlanEntry> {
+        debug!(src = %src.display(), dst = %dst.display(), "Force removing destination");
+        let entry = PlanEntry::new(src, dst, Outcome::ForceReplaced);
+        let is_symlink = dst.symlink_metadata().is_ok_and(|meta| meta.is_symlink());
+        Ok(if is_symlink && mode == Mode::Symlink {
+            entry.step(
+                Action::ReplaceSymlink {
+                    target: target.to_owned(),
+                    path: dst.to_owned(),
+                },
+                "forced: destination is a different symlink",
+            )
+        } else {
+            entry
+                .step(
+                    Action::RemoveFile {
+                        path: dst.to_owned(),
+                    },
+                    "forced: destination already exists",
+                )
+                .step(
+                    ctx.install_action(src, dst, target, mode)?,
+                    "destination removed",
+                )
+        })
+    }
+
+    /// Plan for an existing `dst` that was installed by dotr, if it was
+    ///
+    /// That is a symlink pointing to `target`, a hard link to `src`, an
+    /// unmodified copy of `src`, or anything recorded as installed from
+    /// another source (e.g. before it was renamed) and left untouched since.
+    /// It's either already correct, or can be safely replaced, e.g. when
+    /// `mode` changed.
+    fn plan_installed(
+        &self,
+        ctx: &Ctx,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+    ) -> Result<Option<PlanEntry>> {
+        let dst_type = dst
+            .symlink_metadata()
+            .at(dst, "read metadata of")?
+            .file_type();
+
+        if let Some(record) = ctx.state.get(dst)
+            && ctx.superseded(record)
+            && record.is_intact()?
+        {
+            let reason = format!("installed from {} before", record.src.display());
+            return Ok(Some(ctx.plan_reinstall(src, dst, target, mode, reason)?));
+        }
+
+        // How `dst` is currently installed, and whether it's up to date
+        let (current, up_to_date) = if dst_type.is_symlink() {
+            match link_match(dst, target)? {
+                LinkMatch::Elsewhere => return Ok(None),
+                LinkMatch::Equivalent if self.rewrite_equivalent && mode == Mode::Symlink => {
+                    let reason = "points to the source through another path".to_owned();
+                    return Ok(Some(ctx.plan_reinstall(src, dst, target, mode, reason)?));
                 }
-            } else if self.force {
-                warn!(src = %src.display(), dst = %dst.display(), "Destination is not a symlink, refusing to remove");
-            } else {
-                warn!(src = %src.display(), dst = %dst.display(), "Destination exists but is not a symlink");
+                _ => {}
             }
+            (Mode::Symlink, true)
+        } else if !dst_type.is_file() {
+            return Ok(None);
+        } else if is_same_file(src, dst)? {
+            (Mode::Hardlink, true)
         } else {
-            debug!(src = %src.display(), dst = %dst.display(), "Destination doesn't exist - nothing to unlink");
+            let dst_hash = content_hash(dst)?;
+            match ctx.state.copy_of(dst, src) {
+                Some(record) if record.hash.as_ref() == Some(&dst_hash) => (
+                    record.mode,
+                    record.mode == mode && ctx.expected_hash(src, mode)? == dst_hash,
+                ),
+                Some(_) => return Ok(None),
+                // Not ours, but as good as what would be written
+                None if mode.writes_content() && ctx.expected_hash(src, mode)? == dst_hash => {
+                    return Ok(Some(PlanEntry::new(src, dst, Outcome::Updated).step(
+                        ctx.install_action(src, dst, target, mode)?,
+                        "destination identical to the source",
+                    )));
+                }
+                None => return Ok(None),
+            }
+        };
+
+        if current == mode && up_to_date {
+            debug!(src = %src.display(), dst = %dst.display(), %mode, "Destination already installed");
+            return Ok(Some(PlanEntry::new(src, dst, Outcome::AlreadyCorrect)));
         }
-        Ok(())
+        let reason = if current != mode {
+            format!("switching from {current} to {mode} mode")
+        } else if mode == Mode::Template {
+            "source or variables changed since rendered".to_owned()
+        } else {
+            "source changed since copied".to_owned()
+        };
+        Ok(Some(ctx.plan_reinstall(src, dst, target, mode, reason)?))
     }
 
-    pub fn link_entry(
+    /// Plan removing `dst` if it's a symlink pointing to `target`, a hard
+    /// link to `src`, or an unmodified copy of `src`
+    fn plan_unlink_to(
         &self,
-        src: &walkdir::DirEntry,
-        src_base: &Path,
-        dst_base: &Path,
-    ) -> io::Result<()> {
-        trace!(path = %src.path().display(), "Walking path");
-
-        let src = src.path();
-        let src_rel = src.strip_prefix(src_base).unwrap();
-
-        let dst = dst_base.join(src_rel);
-        let dst_metadata = dst.symlink_metadata().ok();
-        let dst_type = dst_metadata.map(|m| m.file_type());
-
-        let src_metadata = src.symlink_metadata()?;
-        let src_type = src_metadata.file_type();
-
-        if src_type.is_dir() {
-            return Ok(());
-        } else if src_type.is_file() {
-            trace!(src = %src.display(), dst=%dst.display(), "Source is a file");
-            if dst.exists() || dst.symlink_metadata().is_ok() {
-                if self.force {
-                    if dst_type.is_some_and(|t| t.is_dir()) {
-                        io::Error::other(format!(
-                            "Can't safely remove {} as it's a directory",
-                            dst.display()
-                        ));
-                    }
-                    if !self.dry_run {
-                        debug!(src = %src.display(), dst=%dst.display(), "Force removing destination");
-                        fs::remove_file(&dst)?;
-                    } else {
-                        debug!(src = %src.display(), dst=%dst.display(), "Force removing destination (dry-run)");
-                    }
-                } else {
-                    if dst_type.map(|t| t.is_symlink()).unwrap_or(false) {
-                        let dst_link_dst = dst.read_link()?;
-                        if *dst_link_dst == *src {
-                            debug!(src = %src.display(), dst=%dst.display(), "Destination already points to the source");
-                            return Ok(());
-                        } else {
-                            warn!(src = %src.display(), dst = %dst.display(), dst_dst = %dst_link_dst.display(), "Destination already exists and points elsewhere");
-                        }
-                    } else {
-                        warn!(src = %src.display(), dst=%dst.display(),  "Destination already exists and is not a symlink");
-                    }
-                    return Ok(());
-                }
-            } else if !self.dry_run {
-                trace!(src = %src.display(), dst=%dst.display(), "Creating a base directory (if doesn't exist)");
-                fs::create_dir_all(dst.parent().unwrap())?;
-            }
+        ctx: &Ctx,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+    ) -> Result<PlanEntry> {
+        let Ok(dst_metadata) = dst.symlink_metadata() else {
+            debug!(src = %src.display(), dst = %dst.display(), "Destination doesn't exist - nothing to unlink");
+            return Ok(PlanEntry::new(src, dst, Outcome::Skipped));
+        };
+        let dst_type = dst_metadata.file_type();
+        let remove = |reason: &str| {
+            PlanEntry::new(src, dst, Outcome::Removed).step(
+                Action::RemoveFile {
+                    path: dst.to_owned(),
+                },
+                reason,
+            )
+        };
+
+        if let Some(record) = ctx.state.get(dst)
+            && ctx.superseded(record)
+            && record.is_intact()?
+        {
+            return Ok(remove(&format!(
+                "installed from {} before",
+                record.src.display()
+            )));
+        }
 
-            if !self.dry_run {
-                trace!(src = %src.display(), dst=%dst.display(), "Creating symlink to a src file");
-                std::os::unix::fs::symlink(src, &dst)?;
+        if dst_type.is_symlink() {
+            let dst_link = dst.read_link().at(dst, "read link")?;
+            if points_to(dst, target)? {
+                return Ok(remove("destination points to the source"));
             }
-        } else if src_type.is_symlink() {
-            let src_link = src.read_link()?;
-            trace!(src = %src.display(), dst=%dst.display(), "src-link" = %src_link.display(), "Source is a symlink");
-            if dst.exists() || dst.symlink_metadata().is_ok() {
-                if self.force {
-                    if !self.dry_run {
-                        debug!(src = %src.display(), dst = %dst.display(), "Force removing destination");
-                        fs::remove_file(&dst)?;
-                    } else {
-                        debug!(src = %src.display(), dst = %dst.display(), "Force removing destination (dry-run)");
-                    }
-                } else if Some(src_link.clone()) == dst.read_link().ok() {
-                    debug!(
-                        src = %src.display(), dst = %dst.display(),
-                        "Destination already points to the source (symlink source)"
-                    );
-                    return Ok(());
-                } else {
-                    warn!(src = %src.display(), dst = %dst.display(), "Destination already exists");
-                    return Ok(());
-                }
-            } else if !self.dry_run {
-                trace!(src = %src.display(), dst = %dst.display(), "Creating a base directory (if doesn't exist)");
-                fs::create_dir_all(dst.parent().unwrap())?;
+            if self.force {
+                return Ok(remove("forced: destination points elsewhere"));
             }
-            if !self.dry_run {
-                trace!(src = %src.display(), dst = %dst.display(), "src-link" = %src_link.display(), "Duplicating symlink");
-                std::os::unix::fs::symlink(&src_link, &dst)?;
+            warn!(
+                src = %src.display(),
+                dst = %dst.display(),
+                "dst-link" = %dst_link.display(),
+                "Destination already exists and is a symlink pointing to something else",
+            );
+        } else if dst_type.is_dir() {
+            warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is a directory, refusing to remove");
+        } else if is_same_file(src, dst)? {
+            return Ok(remove("destination is a hard link to the source"));
+        } else if let Some(record) = ctx.state.copy_of(dst, src) {
+            if record.is_intact()? {
+                return Ok(remove("destination is an unmodified copy of the source"));
             }
+            if self.force {
+                return Ok(remove("forced: copy modified since installed"));
+            }
+            warn!(src = %src.display(), dst = %dst.display(), "Destination is a copy modified since it was installed, refusing to remove");
+        } else if self.force {
+            return Ok(remove("forced: destination is not a symlink"));
         } else {
-            warn!(src = %src.display(), dst = %dst.display(), "Skipping unknown source file type");
+            warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is not a symlink");
         }
-        Ok(())
+        Ok(PlanEntry::conflict(src, dst, conflict_kind(ctx, src, dst)?))
     }
 
-    pub fn link(&self, src_base: &Path, dst_base: &Path) -> io::Result<()> {
-        info!(src = %src_base.display(), dst = %dst_base.display(), "Starting link operation");
-
-        if !dst_base.exists() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Destination doesn't exist",
-            ));
+    /// Plan removing a destination installed from a source that's gone, if
+    /// it's still as it was installed
+    fn plan_remove_stale(&self, record: &Record) -> Result<PlanEntry> {
+        let (src, dst) = (&record.src, &record.path);
+        let new = |outcome| PlanEntry::new(src, dst, outcome).kind(EntryKind::of_mode(record.mode));
+        if !record.exists() {
+            debug!(src = %src.display(), dst = %dst.display(), "Stale destination doesn't exist anymore");
+            return Ok(new(Outcome::Skipped));
         }
-
-        if !dst_base.is_dir() {
-            return Err(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                "Destination is not a directory",
+        let dst_metadata = dst.symlink_metadata().at(dst, "read metadata of")?;
+        let remove = |reason| {
+            new(Outcome::Removed).step(
+                Action::RemoveFile {
+                    path: dst.to_owned(),
+                },
+                reason,
+            )
+        };
+        if record.is_intact()? {
+            return Ok(remove("installed from a source that's gone"));
+        }
+        let kind = if dst_metadata.is_dir() {
+            warn!(src = %src.display(), dst = %dst.display(), "Stale destination was replaced with a directory, refusing to remove");
+            ConflictKind::DirectoryInTheWay
+        } else if self.force {
+            return Ok(remove(
+                "forced: installed from a source that's gone, and modified since",
             ));
+        } else {
+            warn!(src = %src.display(), dst = %dst.display(), "Stale destination was modified since it was installed, refusing to remove");
+            ConflictKind::Modified
+        };
+        Ok(PlanEntry::conflict(src, dst, kind).kind(EntryKind::of_mode(record.mode)))
+    }
+
+    /// Plan linking a single file or symlink
+    fn plan_link_file(&self, ctx: &Ctx, src: &Path, dst: &Path, mode: Mode) -> Result<PlanEntry> {
+        trace!(path = %src.display(), "Walking path");
+
+        let src_type = src
+            .symlink_metadata()
+            .at(src, "read metadata of")?
+            .file_type();
+
+        if src_type.is_file() {
+            trace!(src = %src.display(), dst = %dst.display(), %mode, "Source is a file");
+            let mode = effective_mode(src, dst, mode)?;
+            if mode.writes_content() && ctx.state_file.is_none() {
+                warn!(src = %src.display(), dst = %dst.display(), %mode, "No state to keep track of the installed file in, skipping");
+                let what = if mode == Mode::Template {
+                    "rendered templates"
+                } else {
+                    "copies"
+                };
+                return Ok(PlanEntry::skipped(
+                    src,
+                    dst,
+                    format!("{what} require the state, which is disabled or has no directory"),
+                )
+                .kind(EntryKind::of_mode(mode)));
+            }
+            Ok(self
+                .plan_link_to(ctx, src, dst, &self.symlink_target(src, dst), mode)?
+                .kind(EntryKind::of_mode(mode)))
+        } else if src_type.is_symlink() {
+            let src_link = src.read_link().at(src, "read link")?;
+            trace!(src = %src.display(), dst = %dst.display(), "src-link" = %src_link.display(), "Source is a symlink");
+            // Symlinks are duplicated as-is, regardless of `mode`
+            Ok(self
+                .plan_link_to(ctx, src, dst, &src_link, Mode::Symlink)?
+                .kind(EntryKind::Symlink))
+        } else {
+            warn!(src = %src.display(), dst = %dst.display(), "Skipping unknown source file type");
+            Ok(PlanEntry::new(src, dst, Outcome::Skipped))
         }
+    }
 
-        let dst_base = dst_base.canonicalize()?;
-        let src_base = src_base.canonicalize()?;
+    /// Plan unlinking a single file or symlink
+    fn plan_unlink_file(&self, ctx: &Ctx, src: &Path, dst: &Path, mode: Mode) -> Result<PlanEntry> {
+        trace!(path = %src.display(), "Walking path");
 
-        assert!(dst_base.is_absolute());
-        assert!(src_base.is_absolute());
+        let src_type = src
+            .symlink_metadata()
+            .at(src, "read metadata of")?
+            .file_type();
 
-        let mut iter = WalkDir::new(&src_base).into_iter();
+        if src_type.is_file() {
+            trace!(src = %src.display(), dst = %dst.display(), "Unlink a file");
+            Ok(self
+                .plan_unlink_to(ctx, src, dst, src)?
+                .kind(EntryKind::of_mode(mode)))
+        } else if src_type.is_symlink() {
+            trace!(src = %src.display(), dst = %dst.display(), "Unlink a symlink");
+            Ok(self
+                .plan_unlink_to(ctx, src, dst, &src.read_link().at(src, "read link")?)?
+                .kind(EntryKind::Symlink))
+        } else {
+            warn!(src = %src.display(), dst = %dst.display(), "Skipping unknown source file type");
+            Ok(PlanEntry::new(src, dst, Outcome::Skipped))
+        }
+    }
+
+    /// Walk the source tree, calling `f` for every entry that should be
+    /// handled, along with its destination.
+    ///
+    /// Skips `.dotr` config and `.dotrignore` files, directories that
+    /// should not be traversed and anything excluded by `.dotrignore` files,
+    /// `.dotr` `ignore` lists or [`Dotr::add_exclude`]. Only the best
+    /// matching `##` variant of an entry, and directories whose `.dotr`
+    /// `hosts` and `profiles` match, are walked. Reports
+    /// directories configured with `traverse = "link"` as a single
+    /// [`WalkItem::LinkedDir`]. `.tmpl` files are reported as templates,
+    /// with the suffix removed from their destination. Entries are visited
+    /// in file name order, so plans are deterministic. Directories that
+    /// can't be read are reported as [`WalkItem::Failed`] when keeping going.
+    ///
+    /// With `only`, nothing but the directories leading to that source path
+    /// and the path itself is walked.
+    fn walk(
+        &self,
+        ctx: &Ctx,
+        only: Option<&Path>,
+        mut f: impl FnMut(WalkItem) -> Result<()>,
+    ) -> Result<()> {
+        /// A directory enclosing the entry being walked
+        struct Scope {
+            src: PathBuf,
+            dst: PathBuf,
+            mode: Mode,
+            files: BTreeMap<String, FileConfig>,
+            /// See [`Selector::select`]
+            variants: HashMap<OsString, Option<OsString>>,
+        }
+
+        let src_base = ctx.src_base.as_path();
+        let mut excludes = Excludes::new(src_base, &self.excludes)?;
+        // Outermost first
+        let mut scopes: Vec<Scope> = vec![];
+        let mut iter = WalkDir::new(src_base).sort_by_file_name().into_iter();
         while let Some(entry) = iter.next() {
             let entry = match entry {
                 Ok(e) => e,
                 Err(e) => {
-                    warn!("Error walking: {}", e);
+                    let src = e.path().unwrap_or(src_base).to_owned();
+                    let dst = match scopes.iter().rev().find(|s| src.starts_with(&s.src)) {
+                        Some(scope) => scope
+                            .dst
+                            .join(ctx.dst_rel(src.strip_prefix(&scope.src).unwrap())),
+                        None => ctx.dst_base.clone(),
+                    };
+                    let error = Error::Io {
+                        path: src.clone(),
+                        op: "walk",
+                        source: e.into(),
+                    };
+                    self.walk_failed(src, dst, error, &mut f)?;
                     continue;
                 }
             };
+            let src = entry.path();
 
-            // Skip .dotr config files
-            if entry.path().file_name() == Some(OsStr::new(DOTR_CONFIG_FILE)) {
+            // Skip .dotr config and .dotrignore files
+            if src.file_name() == Some(OsStr::new(DOTR_CONFIG_FILE))
+                || src.file_name() == Some(OsStr::new(DOTR_IGNORE_FILE))
+            {
                 continue;
             }
 
-            if entry.file_type().is_dir() {
-                if !should_traverse(&entry) {
+            let is_dir = entry.file_type().is_dir();
+            if only.is_some_and(|only| !only.starts_with(src)) {
+                if is_dir {
                     iter.skip_current_dir();
-                    continue;
                 }
+                continue;
+            }
+            if src != src_base && excludes.is_excluded(src, is_dir) {
+                if is_dir {
+                    iter.skip_current_dir();
+                }
+                continue;
+            }
 
-                // Check .dotr config for non-root directories
-                if entry.path() != src_base.as_path() {
-                    let config = read_dir_config(entry.path());
-                    if config.traverse == Some(Traverse::Link) {
-                        debug!(path = %entry.path().display(), "Linking directory per .dotr traverse=link");
-                        self.link_dir(entry.path(), &src_base, &dst_base)?;
+            while scopes.last().is_some_and(|s| !src.starts_with(&s.src)) {
+                scopes.pop();
+            }
+            // `None` only for the root
+            let parent = scopes.last();
+            // Name to install as, after variant selection
+            let name = match parent.and_then(|p| p.variants.get(entry.file_name())) {
+                Some(Some(base)) => base.as_os_str(),
+                Some(None) => {
+                    debug!(path = %src.display(), "Skipping variant not selected");
+                    if is_dir {
                         iter.skip_current_dir();
-                        continue;
                     }
+                    continue;
                 }
+                None => entry.file_name(),
+            };
+            let mut dst = match parent {
+                Some(parent) => parent.dst.join(ctx.dst_rel(Path::new(name))),
+                None => ctx.dst_base.clone(),
+            };
+            let parent_mode = parent.map_or(self.mode, |p| p.mode);
 
+            if !is_dir {
+                let mut mode = parent
+                    .and_then(|p| p.files.get(entry.file_name().to_str()?)?.mode)
+                    .unwrap_or(parent_mode);
+                if let Some(stem) = name
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(TEMPLATE_SUFFIX))
+                    .filter(|stem| !stem.is_empty())
+                {
+                    dst.set_file_name(ctx.dst_rel(Path::new(stem)));
+                    mode = Mode::Template;
+                }
+                f(WalkItem::Entry {
+                    src: src.to_owned(),
+                    dst,
+                    mode,
+                })?;
                 continue;
             }
 
-            self.link_entry(&entry, &src_base, &dst_base)?;
-        }
-
-        Ok(())
-    }
-
-    pub fn unlink(&self, src_base: &Path, dst_base: &Path) -> io::Result<()> {
-        info!(src = %src_base.display(), dst = %dst_base.display(), "Starting unlink operation");
-
-        let dst_base = dst_base.canonicalize()?;
-        let src_base = src_base.canonicalize()?;
-
-        assert!(dst_base.is_absolute());
-        assert!(src_base.is_absolute());
+            if !should_traverse(&entry) {
+                iter.skip_current_dir();
+                continue;
+            }
 
-        let mut iter = WalkDir::new(&src_base).into_iter();
-        while let Some(entry) = iter.next() {
-            let entry = match entry {
-                Ok(e) => e,
+            let config = match read_dir_config(src) {
+                Ok(config) => config,
                 Err(e) => {
-                    warn!("Error walking: {}", e);
+                    self.walk_failed(src.to_owned(), dst, e.into(), &mut f)?;
+                    iter.skip_current_dir();
                     continue;
                 }
             };
 
-            if entry.path().file_name() == Some(OsStr::new(DOTR_CONFIG_FILE)) {
-                continue;
-            }
+            // `traverse` and `target` only apply to non-root directories
+            if src != src_base {
+                if config.dot_prefix.is_some() {
+                    warn!(path = %src.display(), "`dot_prefix` is only supported in the root .dotr, ignoring");
+                }
 
-            if entry.file_type().is_dir() {
-                if !should_traverse(&entry) {
+                if !ctx.selector.applies(&config.hosts, &config.profiles) {
+                    debug!(path = %src.display(), "Skipping directory for other hosts or profiles");
                     iter.skip_current_dir();
                     continue;
                 }
 
-                if entry.path() != src_base.as_path() {
-                    let config = read_dir_config(entry.path());
-                    if config.traverse == Some(Traverse::Link) {
-                        debug!(path = %entry.path().display(), "Unlinking directory per .dotr traverse=link");
-                        self.unlink_dir(entry.path(), &src_base, &dst_base)?;
-                        iter.skip_current_dir();
-                        continue;
-                    }
+                if let Some(target) = &config.target {
+                    dst = ctx.dst_base.join(target);
+                    debug!(src = %src.display(), dst = %dst.display(), "Retargeting directory per .dotr target");
                 }
 
-                continue;
+                if config.traverse == Some(Traverse::Link) {
+                    f(WalkItem::LinkedDir {
+                        src: src.to_owned(),
+                        dst,
+                    })?;
+                    iter.skip_current_dir();
+                    continue;
+                }
             }
 
-            self.unlink_entry(&entry, &src_base, &dst_base)?;
+            let variants = match ctx
+                .selector
+                .select(src)
+                .and_then(|variants| excludes.enter_dir(src, &config.ignore).map(|()| variants))
+            {
+                Ok(variants) => variants,
+                Err(e) => {
+                    self.walk_failed(src.to_owned(), dst, e, &mut f)?;
+                    iter.skip_current_dir();
+                    continue;
+                }
+            };
+            scopes.push(Scope {
+                src: src.to_owned(),
+                dst,
+                mode: config.mode.unwrap_or(parent_mode),
+                files: config.files,
+                variants,
+            });
         }
 
         Ok(())
     }
 
-    pub fn unlink_entry(
+    /// Report `src`, a directory [`Dotr::walk`] can't get into due to
+    /// `error`, as [`WalkItem::Failed`] when keeping going, or fail
+    fn walk_failed(
+        &self,
+        src: PathBuf,
+        dst: PathBuf,
+        error: Error,
+        f: &mut impl FnMut(WalkItem) -> Result<()>,
+    ) -> Result<()> {
+        if self.on_error != OnError::KeepGoing {
+            return Err(error);
+        }
+        warn!(src = %src.display(), dst = %dst.display(), %error, "Can't walk directory, skipping it");
+        f(WalkItem::Failed { src, dst, error })
+    }
+
+    /// [`Dotr::walk`] every layer, in order, calling `f` for every entry
+    /// along with its layer's [`Ctx`] and whether it's shadowed
+    ///
+    /// An entry is shadowed if a later layer has an entry for the same
+    /// destination, or for a directory linked as a whole that contains it
+    /// (or vice versa).
+    fn walk_layers(
         &self,
-        src: &walkdir::DirEntry,
+        ctxs: &[Ctx],
+        mut f: impl FnMut(&Ctx, WalkItem, bool) -> Result<()>,
+    ) -> Result<()> {
+        let mut items = vec![];
+        for (layer, ctx) in ctxs.iter().enumerate() {
+            self.walk(ctx, None, |item| {
+                items.push((layer, item));
+                Ok(())
+            })?;
+        }
+
+        // Last layer of every destination, and of the linked directories
+        let mut dsts = BTreeMap::new();
+        let mut linked_dirs = HashMap::new();
+        for (layer, item) in &items {
+            dsts.insert(item.dst().to_owned(), *layer);
+            if let WalkItem::LinkedDir { dst, .. } = item {
+                linked_dirs.insert(dst.clone(), *layer);
+            }
+        }
+
+        for (layer, item) in items {
+            let dst = item.dst();
+            let later = |l: &usize| layer < *l;
+            // Failures are reported regardless
+            let shadowed = !matches!(item, WalkItem::Failed { .. })
+                && (dsts.get(dst).is_some_and(later)
+                    || dst
+                        .ancestors()
+                        .skip(1)
+                        .any(|dir| linked_dirs.get(dir).is_some_and(later))
+                    || (matches!(item, WalkItem::LinkedDir { .. })
+                        && dsts
+                            .range(dst.to_owned()..)
+                            .take_while(|(path, _)| path.starts_with(dst))
+                            .any(|(_, l)| later(l))));
+            f(&ctxs[layer], item, shadowed)?;
+        }
+        Ok(())
+    }
+
+    /// [`Ctx`] of every layer
+    fn layer_ctxs(&self, src_bases: &[impl AsRef<Path>], dst_base: &Path) -> Result<Vec<Ctx>> {
+        if src_bases.is_empty() {
+            return Err(Error::NoSource);
+        }
+        let mut ctxs = src_bases
+            .iter()
+            .map(|src_base| Ctx::new(self, src_base.as_ref(), dst_base))
+            .collect::<Result<Vec<_>>>()?;
+        let layers: Vec<_> = ctxs.iter().map(|ctx| ctx.src_base.clone()).collect();
+        let backup_id = ctxs[0].backup_id.clone();
+        for ctx in &mut ctxs {
+            ctx.layers = layers.clone();
+            ctx.backup_id = backup_id.clone();
+        }
+        Ok(ctxs)
+    }
+
+    fn plan_link_item(&self, ctx: &Ctx, item: &WalkItem) -> Result<PlanEntry> {
+        match item {
+            WalkItem::LinkedDir { src, dst } => {
+                debug!(path = %src.display(), "Linking directory per .dotr traverse=link");
+                let target = self.symlink_target(src, dst);
+                Ok(self
+                    .plan_link_to(ctx, src, dst, &target, Mode::Symlink)?
+                    .kind(EntryKind::DirLink))
+            }
+            WalkItem::Entry { src, dst, mode } => self.plan_link_file(ctx, src, dst, *mode),
+            WalkItem::Failed { src, dst, error } => Ok(PlanEntry::failed(src, dst, error)),
+        }
+    }
+
+    fn plan_unlink_item(&self, ctx: &Ctx, item: &WalkItem) -> Result<PlanEntry> {
+        match item {
+            WalkItem::LinkedDir { src, dst } => {
+                debug!(path = %src.display(), "Unlinking directory per .dotr traverse=link");
+                Ok(self
+                    .plan_unlink_to(ctx, src, dst, src)?
+                    .kind(EntryKind::DirLink))
+            }
+            WalkItem::Entry { src, dst, mode } => self.plan_unlink_file(ctx, src, dst, *mode),
+            WalkItem::Failed { src, dst, error } => Ok(PlanEntry::failed(src, dst, error)),
+        }
+    }
+
+    /// Plan handling the single source entry `src` with `plan_item`, if
+    /// walking `src_base` reaches it
+    fn plan_entry(
+        &self,
+        src: &Path,
         src_base: &Path,
         dst_base: &Path,
-    ) -> io::Result<()> {
-        trace!(path = %src.path().display(), "Walking path");
+        plan_item: impl Fn(&Ctx, &WalkItem) -> Result<PlanEntry>,
+    ) -> Result<Plan> {
+        let ctx = Ctx::new(self, src_base, dst_base)?;
+        let mut plan = self.new_plan(ctx.state_file.clone());
+        // Like `ctx.src_base`, without following `src` itself if a symlink
+        let Some(name) = src.file_name() else {
+            return Ok(plan);
+        };
+        let src = std::path::absolute(src).at(src, "resolve")?;
+        let src = real_path(src.parent().unwrap()).join(name);
+        if !src.starts_with(&ctx.src_base) {
+            return Ok(plan);
+        }
+        // Its destination and mode depend on every `.dotr` above it
+        let mut found = None;
+        self.walk(&ctx, Some(&src), |item| {
+            let failed = matches!(item, WalkItem::Failed { .. });
+            if item.src() == src || (failed && src.starts_with(item.src())) {
+                found = Some(item);
+            }
+            Ok(())
+        })?;
+        if let Some(item) = found {
+            let entry = plan_item(&ctx, &item);
+            plan.push(self.or_failed(item.src(), item.dst(), entry)?);
+        }
+        Ok(plan)
+    }
 
-        let src = src.path();
-        let src_rel = src.strip_prefix(src_base).unwrap();
+    /// Compute what [`Dotr::link_entry`] would do, without touching the
+    /// destination
+    pub fn plan_link_entry(&self, src: &Path, src_base: &Path, dst_base: &Path) -> Result<Plan> {
+        self.plan_entry(src, src_base, dst_base, |ctx, item| {
+            self.plan_link_item(ctx, item)
+        })
+    }
 
-        let dst = dst_base.join(src_rel);
+    /// Link a single entry of `src_base`: a file, a symlink or a directory
+    /// configured with `traverse = "link"`, exactly as [`Dotr::link`] would
+    ///
+    /// Does nothing if [`Dotr::link`] wouldn't handle `src`, e.g. if it's
+    /// excluded, not the selected variant, or not inside `src_base`.
+    pub fn link_entry(&self, src: &Path, src_base: &Path, dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_link_entry(src, src_base, dst_base)?)
+    }
 
-        let src_metadata = src.symlink_metadata()?;
-        let src_type = src_metadata.file_type();
+    /// Compute what [`Dotr::unlink_entry`] would do, without touching the
+    /// destination
+    pub fn plan_unlink_entry(&self, src: &Path, src_base: &Path, dst_base: &Path) -> Result<Plan> {
+        self.plan_entry(src, src_base, dst_base, |ctx, item| {
+            self.plan_unlink_item(ctx, item)
+        })
+    }
 
-        if src_type.is_dir() {
-            return Ok(());
-        } else if src_type.is_file() {
-            trace!(src = %src.display(), dst = %dst.display(), "Unlink a file");
-            let dst_metadata = dst.symlink_metadata();
-            // exists follows symlinks :/
-            if dst.exists() || dst_metadata.is_ok() {
-                let dst_metadata = dst_metadata?;
-                if self.force {
-                    if !self.dry_run {
-                        debug!(src = %src.display(), dst = %dst.display(), "Force removing");
-                        fs::remove_file(&dst)?;
-                        return Ok(());
-                    } else {
-                        debug!(src = %src.display(), dst = %dst.display(), "Force removing (dry run)");
-                    }
-                } else if dst_metadata.file_type().is_file() {
-                    warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is a file");
-                    return Ok(());
-                } else if dst_metadata.file_type().is_dir() {
-                    warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is a directory");
-                    return Ok(());
-                } else if dst_metadata.file_type().is_symlink() {
-                    let dst_link = dst.read_link()?;
-                    if dst_link != src {
-                        warn!(src = %src.display(), dst = %dst.display(), "Destination already exists and is a symlink pointing to something else");
-                        return Ok(());
-                    } else if !self.dry_run {
-                        fs::remove_file(&dst)?;
-                    }
-                } else {
-                    warn!(src = %src.display(), dst = %dst.display(), "Destination exists and is of unknown file type");
-                }
+    /// Unlink a single entry of `src_base`, as [`Dotr::link_entry`] linked
+    /// it
+    pub fn unlink_entry(&self, src: &Path, src_base: &Path, dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_unlink_entry(src, src_base, dst_base)?)
+    }
+
+    /// Compute everything [`Dotr::link`] would do, without touching the
+    /// destination
+    pub fn plan_link(&self, src_base: &Path, dst_base: &Path) -> Result<Plan> {
+        self.plan_link_layers(&[src_base], dst_base)
+    }
+
+    /// Compute everything [`Dotr::link_layers`] would do, without touching
+    /// the destination
+    pub fn plan_link_layers(
+        &self,
+        src_bases: &[impl AsRef<Path>],
+        dst_base: &Path,
+    ) -> Result<Plan> {
+        info!(dst = %dst_base.display(), "Planning link operation");
+
+        if !dst_base.exists() {
+            return Err(Error::DestinationMissing {
+                path: dst_base.to_owned(),
+            });
+        }
+
+        if !dst_base.is_dir() {
+            return Err(Error::DestinationNotDir {
+                path: dst_base.to_owned(),
+            });
+        }
+
+        let ctxs = self.layer_ctxs(src_bases, dst_base)?;
+
+        let mut plan = self.new_plan(ctxs[0].state_file.clone());
+        self.walk_layers(&ctxs, |ctx, item, shadowed| {
+            let entry = if shadowed {
+                warn!(src = %item.src().display(), dst = %item.dst().display(), "Shadowed by a later source directory");
+                Ok(PlanEntry::new(item.src(), item.dst(), Outcome::Shadowed))
             } else {
-                debug!(src = %src.display(), dst = %dst.display(), "Destination doesn't exist - nothing to unlink");
+                self.plan_link_item(ctx, &item)
+            };
+            plan.push(self.or_failed(item.src(), item.dst(), entry)?);
+            Ok(())
+        })?;
+
+        Ok(plan)
+    }
+
+    /// Compute everything [`Dotr::unlink`] would do, without touching the
+    /// destination
+    pub fn plan_unlink(&self, src_base: &Path, dst_base: &Path) -> Result<Plan> {
+        self.plan_unlink_layers(&[src_base], dst_base)
+    }
+
+    /// Compute everything [`Dotr::unlink_layers`] would do, without touching
+    /// the destination
+    pub fn plan_unlink_layers(
+        &self,
+        src_bases: &[impl AsRef<Path>],
+        dst_base: &Path,
+    ) -> Result<Plan> {
+        info!(dst = %dst_base.display(), "Planning unlink operation");
+
+        let ctxs = self.layer_ctxs(src_bases, dst_base)?;
+
+        let mut plan = self.new_plan(ctxs[0].state_file.clone());
+        let mut walked = HashSet::new();
+        self.walk_layers(&ctxs, |ctx, item, shadowed| {
+            walked.insert(item.dst().to_owned());
+            let entry = if shadowed {
+                Ok(PlanEntry::new(item.src(), item.dst(), Outcome::Shadowed))
+            } else {
+                self.plan_unlink_item(ctx, &item)
+            };
+            plan.push(self.or_failed(item.src(), item.dst(), entry)?);
+            Ok(())
+        })?;
+        for record in stale_records(&ctxs, &walked) {
+            let entry = self.plan_remove_stale(record);
+            plan.push(self.or_failed(&record.src, &record.path, entry)?);
+        }
+
+        Ok(plan)
+    }
+
+    /// Apply `plan`, unless in dry-run mode
+    fn execute(&self, plan: Plan) -> Result<Report> {
+        if self.dry_run {
+            plan.report().into_result()
+        } else {
+            plan.apply()
+        }
+    }
+
+    pub fn link(&self, src_base: &Path, dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_link(src_base, dst_base)?)
+    }
+
+    /// Link several source directories (layers) to the same destination
+    ///
+    /// For the same destination path, a later layer's entry wins over an
+    /// earlier one's, which is reported as [`Outcome::Shadowed`].
+    pub fn link_layers(&self, src_bases: &[impl AsRef<Path>], dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_link_layers(src_bases, dst_base)?)
+    }
+
+    pub fn unlink(&self, src_base: &Path, dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_unlink(src_base, dst_base)?)
+    }
+
+    /// Unlink what [`Dotr::link_layers`] linked
+    pub fn unlink_layers(&self, src_bases: &[impl AsRef<Path>], dst_base: &Path) -> Result<Report> {
+        self.execute(self.plan_unlink_layers(src_bases, dst_base)?)
+    }
+
+    /// Report the state of every destination managed by `src_base`
+    ///
+    /// Nothing is modified, regardless of `force` or `dry_run`.
+    pub fn status(&self, src_base: &Path, dst_base: &Path) -> Result<Vec<StatusEntry>> {
+        self.status_layers(&[src_base], dst_base)
+    }
+
+    /// [`Dotr::status`] of several layers (see [`Dotr::link_layers`]),
+    /// leaving out shadowed entries
+    pub fn status_layers(
+        &self,
+        src_bases: &[impl AsRef<Path>],
+        dst_base: &Path,
+    ) -> Result<Vec<StatusEntry>> {
+        info!(dst = %dst_base.display(), "Starting status operation");
+
+        let ctxs = self.layer_ctxs(src_bases, dst_base)?;
+
+        let mut entries = vec![];
+        let mut walked = HashSet::new();
+        self.walk_layers(&ctxs, |ctx, item, shadowed| {
+            walked.insert(item.dst().to_owned());
+            if shadowed {
                 return Ok(());
             }
-        } else if src_type.is_symlink() {
-            let src_link = src.read_link()?;
-            trace!(src = %src.display(), dst = %dst.display(),  "Unlink a symlink");
-            let dst_metadata = dst.symlink_metadata();
-            // exists follows symlinks :/
-            if dst.exists() || dst_metadata.is_ok() {
-                let dst_metadata = dst_metadata?;
-                if self.force {
-                    if !self.dry_run {
-                        fs::remove_file(&dst)?;
+            let (src, dst, kind, expected, mode) = match item {
+                WalkItem::LinkedDir { src, dst } => {
+                    let expected = self.symlink_target(&src, &dst);
+                    (src, dst, EntryKind::DirLink, expected, Mode::Symlink)
+                }
+                WalkItem::Entry { src, dst, mode } => {
+                    let src_type = src
+                        .symlink_metadata()
+                        .at(&src, "read metadata of")?
+                        .file_type();
+                    if src_type.is_file() {
+                        let mode = effective_mode(&src, &dst, mode)?;
+                        let kind = EntryKind::of_mode(mode);
+                        let expected = self.symlink_target(&src, &dst);
+                        (src, dst, kind, expected, mode)
+                    } else if src_type.is_symlink() {
+                        let expected = src.read_link().at(&src, "read link")?;
+                        (src, dst, EntryKind::Symlink, expected, Mode::Symlink)
+                    } else {
+                        warn!(src = %src.display(), "Skipping unknown source file type");
                         return Ok(());
                     }
-                } else if dst_metadata.file_type().is_file() {
-                    warn!(src = %src.display(), dst = %dst.display(),  "Destination already exists and is a file");
-                    return Ok(());
-                } else if dst_metadata.file_type().is_dir() {
-                    warn!(src = %src.display(), dst = %dst.display(),  "Destination already exists and is a directory");
-                    return Ok(());
-                } else if dst_metadata.file_type().is_symlink() {
-                    let dst_link = dst.read_link()?;
-                    if dst_link != src_link {
-                        warn!(
-                            src = %src.display(),
-                            dst = %dst.display(),
-                            "dst-link" = %dst_link.display(),
-                            "src-link" = %src_link.display(),
-                            "Destination already exists and is a symlink pointing to something else",
-                        );
-                        return Ok(());
-                    } else if !self.dry_run {
-                        fs::remove_file(&dst)?;
+                }
+                // Statuses have no room for failures
+                WalkItem::Failed { error, .. } => return Err(error),
+            };
+            let state = match dst.symlink_metadata() {
+                Err(_) => EntryState::Missing,
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    let dst_link = dst.read_link().at(&dst, "read link")?;
+                    match link_match(&dst, &expected)? {
+                        LinkMatch::Identical => EntryState::Linked,
+                        LinkMatch::Equivalent => EntryState::Equivalent(dst_link),
+                        LinkMatch::Elsewhere => EntryState::PointsElsewhere(dst_link),
                     }
-                } else {
-                    warn!(src = %src.display(), dst = %dst.display(), "Destination exists and is of unknown file type");
                 }
-            } else {
-                debug!(src = %src.display(), dst = %dst.display(), "Destination doesn't exist - nothing to unlink");
-                return Ok(());
+                Ok(meta)
+                    if meta.file_type().is_file()
+                        && kind != EntryKind::DirLink
+                        && is_same_file(&src, &dst)? =>
+                {
+                    EntryState::Hardlinked
+                }
+                // Only files recorded as copies are ours
+                Ok(_) if ctx.state.copy_of(&dst, &src).is_some() => {
+                    if content_hash(&dst)? == ctx.expected_hash(&src, mode)? {
+                        EntryState::Copied
+                    } else {
+                        EntryState::Drifted
+                    }
+                }
+                Ok(_) => EntryState::Conflict,
+            };
+            trace!(src = %src.display(), dst = %dst.display(), ?state, "Entry status");
+            entries.push(StatusEntry {
+                src,
+                dst,
+                kind,
+                state,
+            });
+            Ok(())
+        })?;
+        for record in stale_records(&ctxs, &walked) {
+            if !record.exists() {
+                continue;
             }
-        } else {
-            warn!(src = %src.display(), dst = %dst.display(), "Skipping unknown source file type");
+            entries.push(StatusEntry {
+                src: record.src.clone(),
+                dst: record.path.clone(),
+                kind: EntryKind::of_mode(record.mode),
+                state: EntryState::Stale,
+            });
         }
-        Ok(())
+
+        Ok(entries)
     }
 }
 
@@ -446,6 +1699,169 @@ impl Default for Dotr {
     }
 }
 
+/// Canonical `dst_base`, which has to exist
+fn resolve_dst_base(dst_base: &Path) -> Result<PathBuf> {
+    match dst_base.canonicalize() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::DestinationMissing {
+            path: dst_base.to_owned(),
+        }),
+        res => res.at(dst_base, "resolve"),
+    }
+}
+
+/// `mode`, unless it's a hard link and `dst` can't be on the same
+/// filesystem as `src`, in which case symlinks are used instead
+fn effective_mode(src: &Path, dst: &Path, mode: Mode) -> Result<Mode> {
+    if mode != Mode::Hardlink {
+        return Ok(mode);
+    }
+    let src_dev = src.metadata().at(src, "read metadata of")?.dev();
+    // The destination itself might not exist yet
+    let dst_dev = dst
+        .ancestors()
+        .skip(1)
+        .find_map(|dir| dir.metadata().ok())
+        .map(|meta| meta.dev());
+    if dst_dev == Some(src_dev) {
+        return Ok(Mode::Hardlink);
+    }
+    warn!(src = %src.display(), dst = %dst.display(), "Source and destination are on different filesystems, symlinking instead of hard linking");
+    Ok(Mode::Symlink)
+}
+
+/// Records of what was installed from one of the layers of `ctxs`, but
+/// whose source was removed or renamed since
+///
+/// Sources that still exist (e.g. only excluded this time) are left alone,
+/// as are the `walked` destinations, which some other source accounts for.
+fn stale_records<'a>(
+    ctxs: &'a [Ctx],
+    walked: &'a HashSet<PathBuf>,
+) -> impl Iterator<Item = &'a Record> {
+    // All the layers share the state of the destination
+    ctxs[0].state.records().filter(move |record| {
+        !walked.contains(&record.path)
+            && is_gone(&record.src)
+            && ctxs.iter().any(|ctx| record.src.starts_with(&ctx.src_base))
+    })
+}
+
+/// Is there nothing at `path` anymore?
+///
+/// Not if it merely can't be looked at, e.g. in an unreadable directory.
+pub(crate) fn is_gone(path: &Path) -> bool {
+    matches!(
+        path.symlink_metadata(),
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory)
+    )
+}
+
+/// `path` with `.` and `..` components resolved, without looking at the
+/// filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` with every symlink resolved, as far as it exists
+fn real_path(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(real) = ancestor.canonicalize() {
+            return real.join(path.strip_prefix(ancestor).unwrap());
+        }
+    }
+    path.to_owned()
+}
+
+/// Absolute path a symlink at `link` containing `target` points to (not
+/// following any further symlinks)
+fn resolve_link(link: &Path, target: &Path) -> PathBuf {
+    normalize(&real_path(link.parent().unwrap()).join(target))
+}
+
+/// How what a symlink contains compares to the target it's expected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkMatch {
+    /// Exactly the target
+    Identical,
+    /// Another path to the same file: the other form (absolute or
+    /// relative), or through a symlinked directory or a bind mount
+    Equivalent,
+    Elsewhere,
+}
+
+fn link_match(link: &Path, target: &Path) -> Result<LinkMatch> {
+    let actual = link.read_link().at(link, "read link")?;
+    let resolved = resolve_link(link, target);
+    // Followed all the way, whatever the paths say: the same device and
+    // inode, unless `target` doesn't exist either (e.g. a dangling source
+    // symlink)
+    let points = match (link.metadata(), resolved.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        (Err(_), Ok(_)) => false,
+        _ => resolve_link(link, &actual) == resolved,
+    };
+    Ok(if !points {
+        LinkMatch::Elsewhere
+    } else if actual == target {
+        LinkMatch::Identical
+    } else {
+        LinkMatch::Equivalent
+    })
+}
+
+/// Why the existing `dst`, which is not installed from `src`, is in the way
+fn conflict_kind(ctx: &Ctx, src: &Path, dst: &Path) -> Result<ConflictKind> {
+    let dst_type = dst
+        .symlink_metadata()
+        .at(dst, "read metadata of")?
+        .file_type();
+    Ok(if dst_type.is_dir() {
+        ConflictKind::DirectoryInTheWay
+    } else if ctx.state.copy_of(dst, src).is_some() {
+        ConflictKind::Modified
+    } else {
+        ConflictKind::NotOurs
+    })
+}
+
+/// Does the symlink at `link` point to `target`, through whatever path?
+fn points_to(link: &Path, target: &Path) -> Result<bool> {
+    Ok(link_match(link, target)? != LinkMatch::Elsewhere)
+}
+
+/// Path to `target` from the directory `dir`, both absolute
+fn relative_path(target: &Path, dir: &Path) -> PathBuf {
+    let (target, dir) = (normalize(target), normalize(dir));
+    let common = target
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| "..").collect();
+    relative.extend(target.components().skip(common));
+    relative
+}
+
+/// Are `a` and `b` the same file (same device and inode), i.e. hard links
+/// to each other?
+fn is_same_file(a: &Path, b: &Path) -> Result<bool> {
+    let (a, b) = (
+        a.symlink_metadata().at(a, "read metadata of")?,
+        b.symlink_metadata().at(b, "read metadata of")?,
+    );
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
 fn should_traverse(de: &walkdir::DirEntry) -> bool {
     if !de.path().is_dir() {
         return true;
@@ -1,10 +1,15 @@
 mod opts;
+mod output;
+mod prompt;
 
-use std::process;
+use std::{io, process};
 
+use anyhow::{Context as _, bail};
 use clap::Parser;
-use dotr::Dotr;
-use opts::Options;
+use dotr::{Dotr, Outcome, Plan, StatusEntry};
+use opts::{ConfigFile, Options};
+use output::Printer;
+use prompt::Prompt;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 trait DotrExt {
@@ -23,6 +28,59 @@ impl DotrExt for Dotr {
             dotr = dotr.set_dry_run()
         }
 
+        if opts.backup {
+            dotr = dotr.set_backup();
+        }
+
+        if opts.interactive {
+            dotr = dotr.set_resolver(Prompt::new(io::BufReader::new(io::stdin())));
+        }
+
+        if opts.force_dirs {
+            dotr = dotr.set_force_dirs();
+        }
+
+        if opts.relative {
+            dotr = dotr.set_relative();
+        }
+
+        if opts.rewrite_equivalent {
+            dotr = dotr.set_rewrite_equivalent();
+        }
+
+        if opts.keep_going {
+            dotr = dotr.set_keep_going();
+        }
+
+        if opts.atomic {
+            dotr = dotr.set_atomic();
+        }
+
+        for pattern in opts.exclude {
+            dotr = dotr.add_exclude(pattern);
+        }
+
+        for profile in opts.profile {
+            dotr = dotr.add_profile(profile);
+        }
+
+        if let Some(mode) = opts.mode {
+            dotr = dotr.set_mode(mode);
+        }
+
+        dotr = match opts.state_dir {
+            Some(dir) => dotr.set_state_dir(dir),
+            None => dotr.set_default_state(),
+        };
+
+        if opts.no_state {
+            dotr = dotr.set_no_state();
+        }
+
+        if let Some(path) = opts.vars {
+            dotr = dotr.set_vars_file(path);
+        }
+
         dotr
     }
 }
@@ -37,6 +95,8 @@ fn init_tracing(verbosity: u8) -> anyhow::Result<()> {
     };
 
     let subscriber = FmtSubscriber::builder()
+        // Keep stdout for the output proper
+        .with_writer(std::io::stderr)
         // Use the environment variable, if set, falling back to the specified level if not
         .with_env_filter(EnvFilter::new(
             std::env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV)
@@ -49,26 +109,107 @@ fn init_tracing(verbosity: u8) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn run() -> anyhow::Result<()> {
-    let opts = opts::Options::parse();
+/// Print the plan in dry-run mode, apply it otherwise
+///
+/// Returns the process exit code: 3 if only some entries failed (with
+/// `--keep-going`).
+fn run_plan(opts: &Options, out: &mut Printer, plan: Plan) -> anyhow::Result<i32> {
+    let report = if opts.dry_run {
+        plan.report()
+    } else {
+        match plan.apply_with(|entry, report_entry| out.entry(entry, report_entry)) {
+            Ok(report) => report,
+            Err(dotr::Error::Failures(failures)) => failures.report,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    out.plan(&plan, &report, opts.dry_run);
+    match report.count(Outcome::Failed) {
+        0 => Ok(0),
+        failed if failed == report.entries.len() => bail!("All {failed} entries failed"),
+        _ => Ok(3),
+    }
+}
 
+/// Run the command, returning the process exit code on success
+fn run(opts: Options, out: &mut Printer) -> anyhow::Result<i32> {
     init_tracing(opts.verbose)?;
 
+    let config = match &opts.config {
+        Some(path) => ConfigFile::load(path, true)?,
+        None => match ConfigFile::default_path() {
+            Some(path) => ConfigFile::load(&path, false)?,
+            None => ConfigFile::default(),
+        },
+    };
+    let opts = opts.with_config(config);
+    let dst_dir = opts
+        .dst_dir
+        .clone()
+        .context("No --dst-dir given, and $HOME is not set")?;
+
     let dotr = Dotr::from_opts(opts.clone());
 
     match opts.command {
-        opts::Command::Link => dotr.link(&opts.src_dir, &opts.dst_dir)?,
-        opts::Command::Unlink => dotr.unlink(&opts.src_dir, &opts.dst_dir)?,
+        opts::Command::Link => {
+            return run_plan(&opts, out, dotr.plan_link_layers(&opts.src_dir, &dst_dir)?);
+        }
+        opts::Command::Unlink => {
+            return run_plan(
+                &opts,
+                out,
+                dotr.plan_unlink_layers(&opts.src_dir, &dst_dir)?,
+            );
+        }
+        opts::Command::Adopt { ref paths } => {
+            // Into the layer with the highest precedence
+            let src_dir = opts.src_dir.last().expect("has a default");
+            return run_plan(&opts, out, dotr.plan_adopt(src_dir, &dst_dir, paths)?);
+        }
+        opts::Command::Prune { max_depth } => {
+            return run_plan(
+                &opts,
+                out,
+                dotr.plan_prune(&opts.src_dir, &dst_dir, max_depth)?,
+            );
+        }
+        opts::Command::Restore { ref id } => {
+            return run_plan(&opts, out, dotr.plan_restore(&dst_dir, id.as_deref())?);
+        }
+        opts::Command::Check => {
+            let mut errors = vec![];
+            for src_dir in &opts.src_dir {
+                errors.extend(dotr.check(src_dir)?);
+            }
+            out.check(&errors);
+            if !errors.is_empty() {
+                return Ok(1);
+            }
+        }
+        opts::Command::Status => {
+            let entries = dotr.status_layers(&opts.src_dir, &dst_dir)?;
+            out.status(&entries);
+            if !entries.iter().all(StatusEntry::is_in_sync) {
+                return Ok(1);
+            }
+        }
     }
 
-    Ok(())
+    Ok(0)
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        process::exit(-1);
-    }
+    let opts = Options::parse();
+    let mut out = Printer::new(opts.output);
+    let code = match run(opts, &mut out) {
+        Ok(code) => code,
+        Err(e) => {
+            out.error(&e);
+            -1
+        }
+    };
+    out.finish();
+    process::exit(code);
 }
 
 #[cfg(test)]
@@ -1,29 +1,239 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
 
-use clap::{Parser, Subcommand};
+use anyhow::Context as _;
+use clap::{Parser, Subcommand, ValueEnum};
+use dotr::Mode;
 
-#[derive(Debug, Copy, Clone, Subcommand)]
+/// Format of what's printed on stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    #[default]
+    Human,
+    /// A single JSON document, at the end
+    Json,
+    /// One JSON record per line, as they come
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Subcommand)]
 pub enum Command {
     Link,
     Unlink,
+    /// Report the state of every managed path
+    ///
+    /// Exits with a non-zero code if anything is out of sync.
+    Status,
+    /// Validate every `.dotr` and `.dotrignore` file in the source
+    ///
+    /// Exits with a non-zero code if any is invalid.
+    Check,
+    /// Move destination files or directories into the source and link them
+    /// back
+    Adopt {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Remove symlinks in the destination pointing to files that no longer
+    /// exist in the source
+    Prune {
+        /// How many directory levels of the destination to search
+        #[arg(long, default_value_t = 5)]
+        max_depth: usize,
+    },
+    /// Move backed up files (see `--backup`) back into the destination
+    Restore {
+        /// Backup to restore (defaults to the most recent one)
+        #[arg(long)]
+        id: Option<String>,
+    },
 }
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about)]
 pub struct Options {
+    /// Config file with defaults for these options (defaults to
+    /// `$XDG_CONFIG_HOME/dotr/config.toml`)
+    #[arg(long, value_name = "FILE", env = "DOTR_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Destination directory (defaults to `$HOME`)
     #[arg(long)]
-    pub dst_dir: PathBuf,
-    #[arg(long, default_value = ".")]
-    pub src_dir: PathBuf,
+    pub dst_dir: Option<PathBuf>,
+    /// Source directory (defaults to `.`); can be repeated to layer several,
+    /// later ones taking precedence for the same destination path
+    #[arg(long)]
+    pub src_dir: Vec<PathBuf>,
     #[command(subcommand)]
     pub command: Command,
     /// Dry Run
     #[arg(long)]
     pub dry_run: bool,
     /// Force file deletion/overwritting
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_force")]
     pub force: bool,
+    /// Don't force, even if the config file says so
+    #[arg(long)]
+    pub no_force: bool,
+    /// Move conflicting files into a backup instead of skipping or deleting
+    /// them
+    #[arg(long, overrides_with = "no_backup")]
+    pub backup: bool,
+    /// Don't back up, even if the config file says so
+    #[arg(long)]
+    pub no_backup: bool,
+    /// Ask how to resolve every conflict: overwrite, back up, skip, adopt
+    /// into the source, or show a diff first
+    #[arg(long, conflicts_with_all = ["force", "backup"])]
+    pub interactive: bool,
+    /// Move real directories in the way into the backup as well, rather
+    /// than skipping them
+    #[arg(long, requires = "backup")]
+    pub force_dirs: bool,
+    /// Create symlinks with paths relative to their directory, rather than
+    /// absolute ones
+    #[arg(long, overrides_with = "no_relative")]
+    pub relative: bool,
+    /// Create absolute symlinks, even if the config file says otherwise
+    #[arg(long)]
+    pub no_relative: bool,
+    /// Rewrite symlinks pointing to their source through another path to
+    /// the form `link` creates
+    #[arg(long)]
+    pub rewrite_equivalent: bool,
+    /// Carry on past entries that fail, and list them all at the end (exits
+    /// with 3 if only some failed)
+    #[arg(long)]
+    pub keep_going: bool,
+    /// Undo every change if anything fails, leaving the destination as it
+    /// was
+    #[arg(long, conflicts_with = "keep_going")]
+    pub atomic: bool,
+
+    /// Skip source paths matching a pattern (gitignore syntax, relative to
+    /// the source directory)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// How to install files without a `.dotr` `mode`: `symlink` (default),
+    /// `copy`, `hardlink` or `template`
+    #[arg(long)]
+    pub mode: Option<Mode>,
+
+    /// Select `##profile.<NAME>` variants, and directories for this profile
+    /// (can be repeated)
+    #[arg(long, value_name = "NAME")]
+    pub profile: Vec<String>,
+
+    /// TOML file with variables for templates, in addition to `hostname`,
+    /// `username` and `os`
+    #[arg(long, value_name = "FILE")]
+    pub vars: Option<PathBuf>,
+
+    /// Where to keep track of what was installed (defaults to
+    /// `$XDG_STATE_HOME/dotr`)
+    #[arg(long, value_name = "DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Don't keep track of what was installed (rules out copies and
+    /// templates)
+    #[arg(long, conflicts_with = "state_dir")]
+    pub no_state: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    pub output: Output,
 
     #[clap(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 }
+
+/// Defaults for [`Options`], from the user config file
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub dst_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub src_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub backup: bool,
+    #[serde(default)]
+    pub relative: bool,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub mode: Option<Mode>,
+}
+
+impl ConfigFile {
+    /// `$XDG_CONFIG_HOME/dotr/config.toml`, or `~/.config/dotr/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("dotr").join("config.toml"))
+    }
+
+    /// Load the config file at `path`
+    ///
+    /// Unless `required`, a missing file is the same as an empty one.
+    pub fn load(path: &Path, required: bool) -> anyhow::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Can't read config {}", path.display()));
+            }
+        };
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid config {}", path.display()))?;
+        config.dst_dir = config.dst_dir.map(|dir| expand_tilde(&dir));
+        config.src_dirs = config
+            .src_dirs
+            .iter()
+            .map(|dir| expand_tilde(dir))
+            .collect();
+        Ok(config)
+    }
+}
+
+/// Replace a leading `~` with `$HOME`
+fn expand_tilde(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+impl Options {
+    /// Fill in what wasn't given on the command line from `config`, then
+    /// from the built-in defaults
+    ///
+    /// Flags given either way (e.g. `--relative` or `--no-relative`) win,
+    /// and exclusion patterns add up. Any of `--force`, `--backup` and
+    /// `--interactive` replaces how the config resolves conflicts
+    /// altogether.
+    pub fn with_config(mut self, config: ConfigFile) -> Self {
+        self.dst_dir = self
+            .dst_dir
+            .or(config.dst_dir)
+            .or_else(|| env::var_os("HOME").map(PathBuf::from));
+        if self.src_dir.is_empty() {
+            self.src_dir = config.src_dirs;
+        }
+        if self.src_dir.is_empty() {
+            self.src_dir = vec![PathBuf::from(".")];
+        }
+        if !(self.force || self.backup || self.interactive) {
+            self.force = config.force && !self.no_force;
+            self.backup = config.backup && !self.no_backup;
+        }
+        self.relative |= config.relative && !self.no_relative;
+        self.exclude = config.exclude.into_iter().chain(self.exclude).collect();
+        self.mode = self.mode.or(config.mode);
+        self
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use dotr::{
+    ConfigError, ConflictKind, EntryKind, EntryState, Outcome, Plan, PlanEntry, Report,
+    ReportEntry, StatusEntry, Step,
+};
+use serde::Serialize;
+
+use crate::opts::Output;
+
+/// An entry handled by `link`, `unlink`, `adopt`, `prune` or `restore`
+#[derive(Serialize)]
+struct EntryRecord<'a> {
+    src: &'a Path,
+    dst: &'a Path,
+    kind: Option<EntryKind>,
+    outcome: Outcome,
+    /// Why the destination is in the way, if `conflict-skipped`
+    conflict: Option<&'a ConflictKind>,
+    /// Applied, or in dry-run mode, planned
+    actions: &'a [Step],
+    error: Option<&'a str>,
+}
+
+/// An entry reported by `status`
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct StatusRecord<'a> {
+    src: &'a Path,
+    dst: &'a Path,
+    kind: EntryKind,
+    state: String,
+    /// Where the destination points, if `equivalent` or `elsewhere`
+    target: Option<&'a Path>,
+    in_sync: bool,
+}
+
+/// An invalid config file reported by `check`
+#[derive(Serialize)]
+struct CheckRecord<'a> {
+    path: &'a Path,
+    /// 1-based
+    line: Option<usize>,
+    /// 1-based
+    column: Option<usize>,
+    message: String,
+}
+
+/// Error that ended the command
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    /// See [`dotr::Error::kind`], `other` if not from dotr
+    kind: &'static str,
+    path: Option<&'a Path>,
+    message: String,
+    /// Underlying errors, outermost first
+    causes: Vec<String>,
+}
+
+/// A record along with its type, for NDJSON
+#[derive(Serialize)]
+struct Typed<T> {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    #[serde(flatten)]
+    record: T,
+}
+
+/// Whole output of a command in JSON
+#[derive(Serialize, Default)]
+struct Document {
+    entries: Vec<serde_json::Value>,
+    summary: Option<serde_json::Value>,
+    error: Option<serde_json::Value>,
+}
+
+/// Prints the results of a command on stdout (or `out`), per `--output`
+///
+/// In JSON, nothing is printed until [`Printer::finish`], which prints a
+/// single document with every entry, the summary and the error, if any. In
+/// NDJSON, every entry, the summary and the error are printed right away,
+/// one per line, each with a `type` (`entry`, `summary` or `error`).
+pub(crate) struct Printer<W = io::Stdout> {
+    output: Output,
+    out: W,
+    document: Document,
+    /// Whether plan entries were printed as they were applied already
+    streamed: bool,
+}
+
+impl Printer {
+    pub(crate) fn new(output: Output) -> Self {
+        Printer::to(output, io::stdout())
+    }
+}
+
+impl<W: Write> Printer<W> {
+    pub(crate) fn to(output: Output, out: W) -> Self {
+        Self {
+            output,
+            out,
+            document: Document::default(),
+            streamed: false,
+        }
+    }
+
+    fn record(&mut self, ty: &'static str, record: impl Serialize) {
+        match self.output {
+            Output::Human => {}
+            Output::Json => {
+                let value = serde_json::to_value(record).expect("records serialize");
+                match ty {
+                    "entry" => self.document.entries.push(value),
+                    "summary" => self.document.summary = Some(value),
+                    _ => self.document.error = Some(value),
+                }
+            }
+            Output::Ndjson => writeln!(
+                self.out,
+                "{}",
+                serde_json::to_string(&Typed { ty, record }).expect("records serialize")
+            )
+            .expect(PRINT_FAILED),
+        }
+    }
+
+    fn plan_entry(&mut self, entry: &PlanEntry, report_entry: &ReportEntry) {
+        self.record(
+            "entry",
+            EntryRecord {
+                src: &entry.src,
+                dst: &entry.dst,
+                kind: report_entry.kind,
+                outcome: report_entry.outcome,
+                conflict: report_entry.conflict.as_ref(),
+                actions: &entry.steps,
+                error: report_entry.error.as_deref(),
+            },
+        );
+    }
+
+    /// A plan entry just applied, printed right away in NDJSON
+    pub(crate) fn entry(&mut self, entry: &PlanEntry, report_entry: &ReportEntry) {
+        if self.output == Output::Ndjson {
+            self.plan_entry(entry, report_entry);
+            self.streamed = true;
+        }
+    }
+
+    /// Outcome of a plan, applied (`report`) or not
+    pub(crate) fn plan(&mut self, plan: &Plan, report: &Report, dry_run: bool) {
+        if self.output == Output::Human {
+            if dry_run {
+                writeln!(self.out, "{plan}{report}").expect(PRINT_FAILED);
+            } else {
+                print_report(&mut self.out, report).expect(PRINT_FAILED);
+            }
+            return;
+        }
+        if !self.streamed {
+            for (entry, report_entry) in plan.entries.iter().zip(&report.entries) {
+                self.plan_entry(entry, report_entry);
+            }
+        }
+        let counts: BTreeMap<_, _> = Outcome::ALL
+            .into_iter()
+            .map(|outcome| (outcome, report.count(outcome)))
+            .collect();
+        self.record("summary", counts);
+    }
+
+    pub(crate) fn status(&mut self, entries: &[StatusEntry]) {
+        if self.output == Output::Human {
+            print_status(&mut self.out, entries).expect(PRINT_FAILED);
+            return;
+        }
+        for entry in entries {
+            self.record(
+                "entry",
+                StatusRecord {
+                    src: &entry.src,
+                    dst: &entry.dst,
+                    kind: entry.kind,
+                    state: entry.state.to_string(),
+                    target: match &entry.state {
+                        EntryState::Equivalent(target) | EntryState::PointsElsewhere(target) => {
+                            Some(target)
+                        }
+                        _ => None,
+                    },
+                    in_sync: entry.is_in_sync(),
+                },
+            );
+        }
+        let in_sync = entries.iter().filter(|e| e.is_in_sync()).count();
+        self.record(
+            "summary",
+            BTreeMap::from([
+                ("in-sync", in_sync),
+                ("out-of-sync", entries.len() - in_sync),
+            ]),
+        );
+    }
+
+    pub(crate) fn check(&mut self, errors: &[ConfigError]) {
+        if self.output == Output::Human {
+            for e in errors {
+                writeln!(self.out, "{e}").expect(PRINT_FAILED);
+            }
+            return;
+        }
+        for e in errors {
+            self.record(
+                "entry",
+                CheckRecord {
+                    path: &e.path,
+                    line: e.line_col.map(|(line, _)| line + 1),
+                    column: e.line_col.map(|(_, col)| col + 1),
+                    message: e.to_string(),
+                },
+            );
+        }
+        self.record("summary", BTreeMap::from([("errors", errors.len())]));
+    }
+
+    pub(crate) fn error(&mut self, error: &anyhow::Error) {
+        if self.output == Output::Human {
+            eprintln!("Error: {error}");
+            return;
+        }
+        let dotr_error = error.downcast_ref::<dotr::Error>();
+        self.record(
+            "error",
+            ErrorRecord {
+                kind: dotr_error.map_or("other", dotr::Error::kind),
+                path: dotr_error.and_then(dotr::Error::path),
+                message: error.to_string(),
+                causes: error.chain().skip(1).map(ToString::to_string).collect(),
+            },
+        );
+    }
+
+    /// Print whatever was held back until the end, and hand `out` back
+    pub(crate) fn finish(mut self) -> W {
+        if self.output == Output::Json {
+            writeln!(
+                self.out,
+                "{}",
+                serde_json::to_string_pretty(&self.document).expect("records serialize")
+            )
+            .expect(PRINT_FAILED);
+        }
+        self.out
+    }
+}
+
+/// Panic message when stdout is gone, as [`println!`] would
+const PRINT_FAILED: &str = "failed printing to stdout";
+
+fn print_report(out: &mut impl Write, report: &Report) -> io::Result<()> {
+    for entry in &report.entries {
+        match entry.outcome {
+            Outcome::ConflictSkipped => match &entry.conflict {
+                Some(kind) => writeln!(out, "conflict: {} ({kind})", entry.dst.display())?,
+                None => writeln!(out, "conflict: {}", entry.dst.display())?,
+            },
+            Outcome::Shadowed => writeln!(
+                out,
+                "shadowed: {} by a later source directory",
+                entry.src.display()
+            )?,
+            Outcome::Skipped => {
+                if let Some(reason) = &entry.error {
+                    writeln!(out, "skipped: {}: {reason}", entry.dst.display())?;
+                }
+            }
+            Outcome::Failed => eprintln!(
+                "failed: {}: {}",
+                entry.dst.display(),
+                entry.error.as_deref().unwrap_or_default()
+            ),
+            _ => {}
+        }
+    }
+    writeln!(out, "{report}")
+}
+
+fn print_status(out: &mut impl Write, entries: &[StatusEntry]) -> io::Result<()> {
+    for entry in entries {
+        match &entry.state {
+            EntryState::Equivalent(target) | EntryState::PointsElsewhere(target) => writeln!(
+                out,
+                "{:<10} {:<9} {} -> {}",
+                entry.state,
+                entry.kind,
+                entry.dst.display(),
+                target.display()
+            )?,
+            _ => writeln!(
+                out,
+                "{:<10} {:<9} {}",
+                entry.state,
+                entry.kind,
+                entry.dst.display()
+            )?,
+        }
+    }
+    Ok(())
+}
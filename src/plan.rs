@@ -0,0 +1,401 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use tracing::{debug, info, warn};
+
+use crate::error::IoContext;
+use crate::journal::{Journal, move_path};
+use crate::state::State;
+use crate::{ConflictKind, EntryKind, Error, Outcome, Report, ReportEntry, Result};
+
+/// A single filesystem change
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    /// Create a directory and all its missing parents
+    CreateDirAll { path: PathBuf },
+    /// Create a symlink at `path` pointing to `target`
+    CreateSymlink { target: PathBuf, path: PathBuf },
+    /// Remove the existing symlink at `path` and point it to `target`
+    ReplaceSymlink { target: PathBuf, path: PathBuf },
+    /// Remove a file or a symlink
+    RemoveFile { path: PathBuf },
+    /// Remove an empty directory
+    RemoveDir { path: PathBuf },
+    /// Move `path` to `backup`, creating its parent directories
+    MoveToBackup { path: PathBuf, backup: PathBuf },
+    /// Move `backup` back to `path`, replacing a symlink at `path`, if any
+    RestoreBackup { backup: PathBuf, path: PathBuf },
+    /// Move the file at `path` into the source, as `src`, replacing it if
+    /// it exists
+    Adopt { path: PathBuf, src: PathBuf },
+    /// Copy `src` to `path`, replacing whatever is at `path`
+    CopyFile { src: PathBuf, path: PathBuf },
+    /// Create a hard link to `src` at `path`
+    CreateHardlink { src: PathBuf, path: PathBuf },
+    /// Write `content`, rendered from the template `src`, to `path`,
+    /// replacing whatever is at `path`
+    RenderTemplate {
+        src: PathBuf,
+        path: PathBuf,
+        #[serde(skip)]
+        content: String,
+    },
+}
+
+impl Action {
+    pub(crate) fn apply(&self) -> Result<()> {
+        match self {
+            Action::CreateDirAll { path } => fs::create_dir_all(path).at(path, "create directory"),
+            Action::CreateSymlink { target, path } => {
+                std::os::unix::fs::symlink(target, path).at(path, "create symlink")
+            }
+            Action::ReplaceSymlink { target, path } => {
+                fs::remove_file(path).at(path, "remove")?;
+                std::os::unix::fs::symlink(target, path).at(path, "create symlink")
+            }
+            Action::RemoveFile { path } => fs::remove_file(path).at(path, "remove"),
+            Action::RemoveDir { path } => fs::remove_dir(path).at(path, "remove directory"),
+            Action::MoveToBackup { path, backup } => {
+                let parent = backup.parent().unwrap();
+                fs::create_dir_all(parent).at(parent, "create directory")?;
+                fs::rename(path, backup).at(path, "back up")
+            }
+            Action::RestoreBackup { backup, path } => {
+                if path
+                    .symlink_metadata()
+                    .is_ok_and(|m| m.file_type().is_symlink())
+                {
+                    fs::remove_file(path).at(path, "remove")?;
+                }
+                let parent = path.parent().unwrap();
+                fs::create_dir_all(parent).at(parent, "create directory")?;
+                fs::rename(backup, path).at(backup, "restore")?;
+                crate::backup::remove_empty_parents(backup);
+                Ok(())
+            }
+            Action::Adopt { path, src } => {
+                let parent = src.parent().unwrap();
+                fs::create_dir_all(parent).at(parent, "create directory")?;
+                move_path(path, src).at(path, "adopt")
+            }
+            Action::CopyFile { src, path } => {
+                // Copy aside and rename, so an existing symlink at `path` is
+                // replaced rather than written through
+                let tmp = sibling_path(path, ".dotr-tmp")?;
+                fs::copy(src, &tmp).at(path, "copy to")?;
+                fs::rename(&tmp, path).at(path, "replace")
+            }
+            Action::RenderTemplate { src, path, content } => {
+                let tmp = sibling_path(path, ".dotr-tmp")?;
+                fs::write(&tmp, content).at(&tmp, "write")?;
+                let permissions = src.metadata().at(src, "read metadata of")?.permissions();
+                fs::set_permissions(&tmp, permissions).at(&tmp, "set permissions of")?;
+                fs::rename(&tmp, path).at(path, "replace")
+            }
+            Action::CreateHardlink { src, path } => {
+                fs::hard_link(src, path).at(path, "create hard link")
+            }
+        }
+    }
+}
+
+/// `path` with `suffix` appended, and a number if that's taken, e.g. to
+/// write a file aside before renaming it to `path`
+///
+/// Never anything that exists, as it would be replaced.
+pub(crate) fn sibling_path(path: &Path, suffix: &str) -> Result<PathBuf> {
+    for n in 0..100 {
+        let mut sibling = path.to_owned().into_os_string();
+        sibling.push(suffix);
+        if n > 0 {
+            sibling.push(format!(".{n}"));
+        }
+        match Path::new(&sibling).symlink_metadata() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(sibling.into()),
+            Err(e) => return Err(e).at(Path::new(&sibling), "read metadata of"),
+            Ok(_) => {}
+        }
+    }
+    Err(io::Error::from(io::ErrorKind::AlreadyExists)).at(path, "find a name to set aside")
+}
+
+/// Shell-like rendering, e.g. `ln -s /src/a /dst/a`
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::CreateDirAll { path } => write!(f, "mkdir -p {}", path.display()),
+            Action::CreateSymlink { target, path } => {
+                write!(f, "ln -s {} {}", target.display(), path.display())
+            }
+            Action::ReplaceSymlink { target, path } => {
+                write!(f, "ln -sf {} {}", target.display(), path.display())
+            }
+            Action::RemoveFile { path } => write!(f, "rm {}", path.display()),
+            Action::RemoveDir { path } => write!(f, "rmdir {}", path.display()),
+            Action::MoveToBackup { path, backup } => {
+                write!(f, "mv {} {}", path.display(), backup.display())
+            }
+            Action::RestoreBackup { backup, path } => {
+                write!(f, "mv {} {}", backup.display(), path.display())
+            }
+            Action::Adopt { path, src } => write!(f, "mv {} {}", path.display(), src.display()),
+            Action::CopyFile { src, path } => write!(f, "cp {} {}", src.display(), path.display()),
+            Action::CreateHardlink { src, path } => {
+                write!(f, "ln {} {}", src.display(), path.display())
+            }
+            Action::RenderTemplate { src, path, .. } => {
+                write!(f, "render {} > {}", src.display(), path.display())
+            }
+        }
+    }
+}
+
+/// An [`Action`] together with the reason it was planned
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    pub reason: String,
+}
+
+/// Everything planned for a single source entry
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PlanEntry {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    /// `None` if unknown, e.g. for what's pruned
+    pub kind: Option<EntryKind>,
+    pub outcome: Outcome,
+    /// Why the destination is in the way, if [`Outcome::ConflictSkipped`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<ConflictKind>,
+    pub steps: Vec<Step>,
+    /// Why it couldn't be planned, if [`Outcome::Failed`], or installed, if
+    /// [`Outcome::Skipped`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PlanEntry {
+    pub(crate) fn new(src: &Path, dst: &Path, outcome: Outcome) -> Self {
+        Self {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            kind: None,
+            outcome,
+            conflict: None,
+            steps: vec![],
+            error: None,
+        }
+    }
+
+    /// Entry that can't be installed, due to `reason`
+    pub(crate) fn skipped(src: &Path, dst: &Path, reason: impl Into<String>) -> Self {
+        Self {
+            error: Some(reason.into()),
+            ..Self::new(src, dst, Outcome::Skipped)
+        }
+    }
+
+    /// Entry skipped, as its destination is in the way
+    pub(crate) fn conflict(src: &Path, dst: &Path, kind: ConflictKind) -> Self {
+        Self {
+            conflict: Some(kind),
+            ..Self::new(src, dst, Outcome::ConflictSkipped)
+        }
+    }
+
+    pub(crate) fn kind(self, kind: EntryKind) -> Self {
+        Self {
+            kind: Some(kind),
+            ..self
+        }
+    }
+
+    /// Entry that couldn't be planned, due to `error`
+    pub(crate) fn failed(src: &Path, dst: &Path, error: &Error) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(src, dst, Outcome::Failed)
+        }
+    }
+
+    pub(crate) fn step(mut self, action: Action, reason: impl Into<String>) -> Self {
+        self.steps.push(Step {
+            action,
+            reason: reason.into(),
+        });
+        self
+    }
+
+    fn report(&self) -> ReportEntry {
+        ReportEntry {
+            kind: self.kind,
+            conflict: self.conflict.clone(),
+            error: self.error.clone(),
+            ..ReportEntry::new(&self.src, &self.dst, self.outcome)
+        }
+    }
+}
+
+/// What [`Plan::apply`] does when a step fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum OnError {
+    /// Stop right away
+    #[default]
+    Stop,
+    /// Skip the rest of the failed entry, apply all the others, then fail
+    /// (see [`crate::Dotr::set_keep_going`])
+    KeepGoing,
+    /// Undo everything applied so far (see [`crate::Dotr::set_atomic`])
+    Rollback,
+}
+
+/// Changes computed by [`crate::Dotr::plan_link`] or
+/// [`crate::Dotr::plan_unlink`], to be inspected and then [`Plan::apply`]-ed
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Plan {
+    pub entries: Vec<PlanEntry>,
+    /// Directories already scheduled for creation by earlier entries
+    #[serde(skip)]
+    planned_dirs: HashSet<PathBuf>,
+    /// Where to record what was installed, if anywhere
+    #[serde(skip)]
+    state_file: Option<PathBuf>,
+    #[serde(skip)]
+    on_error: OnError,
+}
+
+impl Plan {
+    pub(crate) fn new(state_file: Option<PathBuf>, on_error: OnError) -> Self {
+        Self {
+            state_file,
+            on_error,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, mut entry: PlanEntry) {
+        // Sibling entries tend to need the same parent directory; only
+        // create it once
+        entry.steps.retain(|step| match &step.action {
+            Action::CreateDirAll { path } => self.planned_dirs.insert(path.clone()),
+            _ => true,
+        });
+        self.entries.push(entry);
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.entries.iter().flat_map(|e| e.steps.iter())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps().next().is_none()
+    }
+
+    /// Outcomes of the plan, without applying it
+    pub fn report(&self) -> Report {
+        Report {
+            entries: self.entries.iter().map(PlanEntry::report).collect(),
+        }
+    }
+
+    /// Execute all the planned steps, in order
+    ///
+    /// Stops at the first failing step, unless planned with
+    /// [`crate::Dotr::set_keep_going`], in which case the rest of the failed
+    /// entry is skipped, every other entry is applied, and the failures
+    /// are returned together as [`Error::Failures`]. The state file is updated
+    /// with whatever was applied, even if a step fails.
+    ///
+    /// If planned with [`crate::Dotr::set_atomic`], the first failing step
+    /// undoes every change made so far instead, leaving the destination
+    /// (and the state) as it was, and returns [`Error::RolledBack`].
+    pub fn apply(&self) -> Result<Report> {
+        self.apply_with(|_, _| {})
+    }
+
+    /// [`Plan::apply`], calling `on_entry` as soon as each entry is done
+    /// with, along with its outcome (e.g. to report progress)
+    ///
+    /// Not called for an entry whose failure stops the run. If planned with
+    /// [`crate::Dotr::set_atomic`], entries reported so far are undone when
+    /// a later step fails.
+    pub fn apply_with(&self, mut on_entry: impl FnMut(&PlanEntry, &ReportEntry)) -> Result<Report> {
+        info!(steps = self.steps().count(), "Applying plan");
+        let mut state = match &self.state_file {
+            Some(path) => Some(State::load(path)?),
+            None => None,
+        };
+        let mut journal = (self.on_error == OnError::Rollback).then(Journal::default);
+        let mut report = self.report();
+        let mut res = Ok(());
+        for (entry, report_entry) in self.entries.iter().zip(&mut report.entries) {
+            let entry_res = entry.steps.iter().try_for_each(|step| {
+                debug!(action = %step.action, reason = %step.reason, "Applying");
+                match &mut journal {
+                    Some(journal) => journal.apply(&step.action)?,
+                    None => step.action.apply()?,
+                }
+                match &mut state {
+                    Some(state) => state.update(&entry.src, &step.action),
+                    None => Ok(()),
+                }
+            });
+            if let Err(e) = entry_res {
+                if self.on_error != OnError::KeepGoing {
+                    res = Err(e);
+                    break;
+                }
+                warn!(src = %entry.src.display(), dst = %entry.dst.display(), error = %e, "Failed, carrying on");
+                report_entry.outcome = Outcome::Failed;
+                report_entry.error = Some(e.to_string());
+            }
+            on_entry(entry, report_entry);
+        }
+        if let Some(journal) = journal {
+            res = match res {
+                Ok(()) => {
+                    journal.commit();
+                    Ok(())
+                }
+                Err(e) => {
+                    warn!(error = %e, "Step failed, rolling back");
+                    return Err(Error::RolledBack {
+                        complete: journal.rollback(),
+                        source: Box::new(e),
+                    });
+                }
+            };
+        }
+        if let (Some(state), Some(path)) = (&mut state, &self.state_file) {
+            state.forget_missing();
+            state.save(path)?;
+        }
+        res?;
+        report.into_result()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            if let Some(error) = &entry.error {
+                writeln!(f, "# {}: {}: {error}", entry.dst.display(), entry.outcome)?;
+                continue;
+            }
+            if entry.steps.is_empty() {
+                if entry.outcome != Outcome::AlreadyCorrect {
+                    writeln!(f, "# {}: {}", entry.dst.display(), entry.outcome)?;
+                }
+                continue;
+            }
+            for step in &entry.steps {
+                writeln!(f, "{}  # {}", step.action, step.reason)?;
+            }
+        }
+        Ok(())
+    }
+}
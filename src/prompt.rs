@@ -0,0 +1,74 @@
+use std::io::{self, BufRead};
+use std::process::{Command, Stdio};
+
+use dotr::{Conflict, Resolution, Resolver};
+
+/// Asks how to resolve every conflict (`--interactive`), reading answers
+/// from `input`
+///
+/// Prompts go to stderr, to keep stdout for the output proper.
+pub(crate) struct Prompt {
+    input: Box<dyn BufRead + Send>,
+    /// Choice made for all the remaining conflicts, if any
+    all: Option<Resolution>,
+}
+
+impl Prompt {
+    pub(crate) fn new(input: impl BufRead + Send + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            all: None,
+        }
+    }
+}
+
+impl Resolver for Prompt {
+    fn resolve(&mut self, conflict: &Conflict) -> Resolution {
+        if let Some(resolution) = self.all {
+            return resolution;
+        }
+        let adopt = if conflict.adoptable { "[a]dopt, " } else { "" };
+        loop {
+            eprint!(
+                "{} already exists: [o]verwrite, [b]ack up and overwrite, [s]kip, {adopt}show [d]iff? (capital letter: all remaining conflicts) ",
+                conflict.dst.display()
+            );
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+                // No more answers to come
+                eprintln!();
+                self.all = Some(Resolution::Skip);
+                return Resolution::Skip;
+            }
+            let answer = line.trim();
+            let resolution = match answer {
+                "o" | "O" => Resolution::Overwrite,
+                "b" | "B" => Resolution::Backup,
+                "s" | "S" => Resolution::Skip,
+                "a" | "A" if conflict.adoptable => Resolution::Adopt,
+                "d" | "D" => {
+                    show_diff(conflict);
+                    continue;
+                }
+                _ => continue,
+            };
+            if answer.chars().all(|c| c.is_ascii_uppercase()) {
+                self.all = Some(resolution);
+            }
+            return resolution;
+        }
+    }
+}
+
+/// Print how the destination differs from the source, on stderr
+fn show_diff(conflict: &Conflict) {
+    let res = Command::new("diff")
+        .arg("-u")
+        .arg(conflict.dst)
+        .arg(conflict.src)
+        .stdout(Stdio::from(io::stderr()))
+        .status();
+    if let Err(e) = res {
+        eprintln!("Can't run diff: {e}");
+    }
+}
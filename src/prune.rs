@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::backup::BACKUP_DIR;
+use crate::error::IoContext;
+use crate::{
+    Action, Dotr, Outcome, Plan, PlanEntry, Report, Result, is_gone, resolve_link, stale_records,
+};
+
+impl Dotr {
+    /// Compute everything [`Dotr::prune`] would do, without touching the
+    /// destination
+    pub fn plan_prune(
+        &self,
+        src_bases: &[impl AsRef<Path>],
+        dst_base: &Path,
+        max_depth: usize,
+    ) -> Result<Plan> {
+        info!(dst = %dst_base.display(), max_depth, "Planning prune operation");
+
+        let ctxs = self.layer_ctxs(src_bases, dst_base)?;
+        let mut walked = HashSet::new();
+        self.walk_layers(&ctxs, |_, item, _| {
+            walked.insert(item.dst().to_owned());
+            Ok(())
+        })?;
+
+        let mut plan = self.new_plan(ctxs[0].state_file.clone());
+        let mut planned = HashSet::new();
+        let iter = WalkDir::new(&ctxs[0].dst_base)
+            .max_depth(max_depth)
+            .sort_by_file_name()
+            .into_iter()
+            // Nor the source itself, when it's inside the destination
+            .filter_entry(|e| {
+                e.file_name() != BACKUP_DIR
+                    && e.file_name() != ".git"
+                    && !ctxs.iter().any(|ctx| e.path() == ctx.src_base)
+            });
+        for entry in iter {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("Error walking: {}", e);
+                    continue;
+                }
+            };
+            if !entry.path_is_symlink() {
+                continue;
+            }
+            let dst = entry.path();
+            let target = resolve_link(dst, &dst.read_link().at(dst, "read link")?);
+            if !ctxs.iter().any(|ctx| target.starts_with(&ctx.src_base)) {
+                continue;
+            }
+            if !is_gone(&target) {
+                continue;
+            }
+            debug!(dst = %dst.display(), target = %target.display(), "Stale link");
+            planned.insert(dst.to_owned());
+            plan.push(PlanEntry::new(&target, dst, Outcome::Removed).step(
+                Action::RemoveFile {
+                    path: dst.to_owned(),
+                },
+                "points into the source, to something that no longer exists",
+            ));
+        }
+        for record in stale_records(&ctxs, &walked) {
+            if !planned.contains(&record.path) {
+                let entry = self.plan_remove_stale(record);
+                plan.push(self.or_failed(&record.src, &record.path, entry)?);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Remove symlinks in the destination (up to `max_depth` levels deep)
+    /// pointing into one of `src_bases`, to something that doesn't exist
+    /// anymore, e.g. after a file was removed from or renamed in the source
+    ///
+    /// Anything else recorded as installed from `src_bases`, that no source
+    /// entry accounts for anymore, is removed as well, if unmodified.
+    pub fn prune(
+        &self,
+        src_bases: &[impl AsRef<Path>],
+        dst_base: &Path,
+        max_depth: usize,
+    ) -> Result<Report> {
+        self.execute(self.plan_prune(src_bases, dst_base, max_depth)?)
+    }
+}
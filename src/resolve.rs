@@ -0,0 +1,107 @@
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use tracing::{debug, warn};
+
+use crate::error::IoContext;
+use crate::{Action, Ctx, Dotr, Mode, Outcome, PlanEntry, Result, conflict_kind};
+
+/// How to resolve a conflict (see [`Resolver`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Remove the destination, and link the source in its place
+    Overwrite,
+    /// Move the destination into the backup (see [`Dotr::set_backup`]), and
+    /// link the source in its place
+    Backup,
+    /// Leave the destination as it is
+    Skip,
+    /// Move the destination into the source, replacing it, and link it back
+    /// (only if [`Conflict::adoptable`], skipped otherwise)
+    Adopt,
+}
+
+/// A destination in the way of linking its source
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    pub src: &'a Path,
+    pub dst: &'a Path,
+    /// Can it be resolved with [`Resolution::Adopt`]? Only if both are
+    /// regular files, and the source is not a template.
+    pub adoptable: bool,
+}
+
+/// Decides how to resolve every conflict met by [`Dotr::link`], e.g. by
+/// asking the user (see [`Dotr::set_resolver`])
+///
+/// `Send`, so [`Dotr`] can still be shared across threads.
+pub trait Resolver: Send {
+    fn resolve(&mut self, conflict: &Conflict) -> Resolution;
+}
+
+impl Dotr {
+    /// Ask `resolver` how to resolve conflicts that would be skipped
+    /// otherwise
+    ///
+    /// Has no effect when forced or backing up, which resolve every conflict
+    /// already. Real directories in the way are never asked about.
+    pub fn set_resolver(self, resolver: impl Resolver + 'static) -> Self {
+        Self {
+            resolver: Some(Mutex::new(Box::new(resolver))),
+            ..self
+        }
+    }
+
+    /// Plan resolving the conflicting `dst` as `resolver` decides
+    pub(crate) fn plan_resolved(
+        &self,
+        ctx: &Ctx,
+        resolver: &Mutex<Box<dyn Resolver>>,
+        src: &Path,
+        dst: &Path,
+        target: &Path,
+        mode: Mode,
+    ) -> Result<PlanEntry> {
+        let conflict = Conflict {
+            src,
+            dst,
+            adoptable: mode != Mode::Template
+                && src
+                    .symlink_metadata()
+                    .at(src, "read metadata of")?
+                    .is_file()
+                && dst
+                    .symlink_metadata()
+                    .at(dst, "read metadata of")?
+                    .is_file(),
+        };
+        let resolution = resolver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .resolve(&conflict);
+        debug!(src = %src.display(), dst = %dst.display(), ?resolution, "Conflict resolved");
+        match resolution {
+            Resolution::Overwrite => self.plan_force_replace(ctx, src, dst, target, mode),
+            Resolution::Backup => self.plan_backup(ctx, src, dst, target, mode),
+            Resolution::Adopt if conflict.adoptable => {
+                Ok(PlanEntry::new(src, dst, Outcome::Adopted)
+                    .step(
+                        Action::Adopt {
+                            path: dst.to_owned(),
+                            src: src.to_owned(),
+                        },
+                        "destination adopted, replacing the source",
+                    )
+                    .step(
+                        ctx.install_action(src, dst, target, mode)?,
+                        "destination moved to the source",
+                    ))
+            }
+            Resolution::Adopt => {
+                warn!(src = %src.display(), dst = %dst.display(), "Destination can't be adopted, skipping");
+                Ok(PlanEntry::conflict(src, dst, conflict_kind(ctx, src, dst)?))
+            }
+            Resolution::Skip => Ok(PlanEntry::conflict(src, dst, conflict_kind(ctx, src, dst)?)),
+        }
+    }
+}
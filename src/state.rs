@@ -0,0 +1,220 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use tracing::debug;
+
+use crate::error::IoContext;
+use crate::{Action, ConfigError, Mode, Result};
+
+/// FNV-1a; only used to notice changes, not for anything security related
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+pub(crate) fn hash(content: &[u8]) -> String {
+    format!("{:016x}", fnv1a(content))
+}
+
+/// [`hash`] of the content of the file at `path`
+pub(crate) fn content_hash(path: &Path) -> Result<String> {
+    Ok(hash(&fs::read(path).at(path, "read")?))
+}
+
+/// `$XDG_STATE_HOME/dotr`, or `~/.local/state/dotr`
+pub(crate) fn default_state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+        .map(|dir| dir.join("dotr"))
+}
+
+/// State file of a given (canonical) destination base
+///
+/// Shared by all the source directories linked to it, as records keep track
+/// of their source anyway.
+pub(crate) fn state_file(state_dir: &Path, dst_base: &Path) -> PathBuf {
+    state_dir.join(format!(
+        "{:016x}.toml",
+        fnv1a(dst_base.as_os_str().as_encoded_bytes())
+    ))
+}
+
+/// A destination installed by dotr
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct Record {
+    pub(crate) path: PathBuf,
+    pub(crate) src: PathBuf,
+    /// How it was installed; symlinked directories and source symlinks are
+    /// recorded as [`Mode::Symlink`]
+    pub(crate) mode: Mode,
+    /// Where the symlink points to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<PathBuf>,
+    /// [`content_hash`] of a copy or rendering, when it was written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) hash: Option<String>,
+}
+
+impl Record {
+    /// Does the destination still exist where it was installed?
+    ///
+    /// Not if its parent got replaced with a symlink since (e.g. to a
+    /// directory linked as a whole), as what's at `path` is then something
+    /// else entirely.
+    pub(crate) fn exists(&self) -> bool {
+        let parent = self.path.parent().unwrap();
+        self.path.symlink_metadata().is_ok()
+            && parent
+                .canonicalize()
+                .is_ok_and(|canonical| canonical == parent)
+    }
+
+    /// Is the destination still exactly as dotr left it?
+    ///
+    /// A hard link whose source is gone can't be told from a regular file
+    /// anymore, so it isn't.
+    pub(crate) fn is_intact(&self) -> Result<bool> {
+        if !self.exists() {
+            return Ok(false);
+        }
+        let meta = self
+            .path
+            .symlink_metadata()
+            .at(&self.path, "read metadata of")?;
+        Ok(match self.mode {
+            Mode::Symlink => {
+                meta.file_type().is_symlink()
+                    && self.target.as_deref()
+                        == Some(self.path.read_link().at(&self.path, "read link")?.as_path())
+            }
+            Mode::Hardlink => {
+                meta.is_file()
+                    && self
+                        .src
+                        .symlink_metadata()
+                        .is_ok_and(|src| src.dev() == meta.dev() && src.ino() == meta.ino())
+            }
+            Mode::Copy | Mode::Template => {
+                meta.is_file() && self.hash.as_deref() == Some(content_hash(&self.path)?.as_str())
+            }
+        })
+    }
+}
+
+/// Everything dotr installed in a destination, so later runs can tell
+/// its own files from the user's
+///
+/// Needed to update or remove copies (and rendered templates) at all, as
+/// they look like any other file, and to clean up after sources that were
+/// removed or renamed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub(crate) struct State {
+    #[serde(default)]
+    installed: Vec<Record>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl State {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).at(path, "read"),
+        };
+        toml::from_str(&content).map_err(|e| ConfigError::from_toml(path, &content, e).into())
+    }
+
+    /// Write the state to `path`, if it changed since loaded
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        debug!(path = %path.display(), "Saving state");
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent).at(parent, "create directory")?;
+        let content = toml::to_string(self)
+            .map_err(io::Error::other)
+            .at(path, "write")?;
+        // Write aside and rename, so the state is never left half-written
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, content).at(&tmp, "write")?;
+        fs::rename(&tmp, path).at(path, "replace")
+    }
+
+    pub(crate) fn records(&self) -> impl Iterator<Item = &Record> {
+        self.installed.iter()
+    }
+
+    /// Record of `path`, if it was installed by dotr
+    pub(crate) fn get(&self, path: &Path) -> Option<&Record> {
+        self.installed.iter().find(|r| r.path == path)
+    }
+
+    /// Record of `path`, if it was installed as a copy (or rendering) of
+    /// `src`
+    pub(crate) fn copy_of(&self, path: &Path, src: &Path) -> Option<&Record> {
+        self.get(path)
+            .filter(|r| r.src == src && r.mode.writes_content())
+    }
+
+    fn record(&mut self, record: Record) {
+        self.forget(&record.path);
+        self.installed.push(record);
+        self.dirty = true;
+    }
+
+    fn forget(&mut self, path: &Path) {
+        let len = self.installed.len();
+        self.installed.retain(|r| r.path != path);
+        self.dirty |= self.installed.len() != len;
+    }
+
+    /// Forget the destinations removed behind dotr's back
+    pub(crate) fn forget_missing(&mut self) {
+        let len = self.installed.len();
+        self.installed.retain(Record::exists);
+        self.dirty |= self.installed.len() != len;
+    }
+
+    /// Account for `action`, once applied as part of the entry for `src`
+    pub(crate) fn update(&mut self, src: &Path, action: &Action) -> Result<()> {
+        let record = |path: &Path, mode, target: Option<&Path>, hash| Record {
+            path: path.to_owned(),
+            src: src.to_owned(),
+            mode,
+            target: target.map(Path::to_owned),
+            hash,
+        };
+        match action {
+            // Adopting a symlink copies it into the source, which is not
+            // something to keep track of
+            Action::CreateSymlink { path, .. } if path == src => self.forget(path),
+            Action::CreateSymlink { target, path } | Action::ReplaceSymlink { target, path } => {
+                self.record(record(path, Mode::Symlink, Some(target), None))
+            }
+            Action::CreateHardlink { path, .. } => {
+                self.record(record(path, Mode::Hardlink, None, None))
+            }
+            Action::CopyFile { path, .. } => {
+                self.record(record(path, Mode::Copy, None, Some(content_hash(path)?)))
+            }
+            Action::RenderTemplate { path, .. } => self.record(record(
+                path,
+                Mode::Template,
+                None,
+                Some(content_hash(path)?),
+            )),
+            Action::RemoveFile { path }
+            | Action::MoveToBackup { path, .. }
+            | Action::RestoreBackup { path, .. }
+            | Action::Adopt { path, .. } => self.forget(path),
+            Action::CreateDirAll { .. } | Action::RemoveDir { .. } => {}
+        }
+        Ok(())
+    }
+}
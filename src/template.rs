@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{env, fs};
+
+use crate::error::IoContext;
+use crate::{ConfigError, Error, Result};
+
+/// Source files with this suffix are rendered as templates, and installed
+/// without it
+pub(crate) const TEMPLATE_SUFFIX: &str = ".tmpl";
+
+/// Name of this machine, if it can be found
+///
+/// As `gethostname(2)` returns it, or failing that, `/etc/hostname` or
+/// `$HOSTNAME`.
+pub(crate) fn hostname() -> Option<String> {
+    let name = |name: String| Some(name.trim().to_owned()).filter(|name| !name.is_empty());
+    gethostname::gethostname()
+        .into_string()
+        .ok()
+        .and_then(name)
+        .or_else(|| fs::read_to_string("/etc/hostname").ok().and_then(name))
+        .or_else(|| env::var("HOSTNAME").ok().and_then(name))
+}
+
+/// Variables available to templates
+#[derive(Debug, Default)]
+pub(crate) struct Vars(BTreeMap<String, String>);
+
+impl Vars {
+    /// Built-in variables (`hostname`, `username`, `os`), plus the ones
+    /// defined in `vars_file` (a TOML table of strings), if any
+    pub(crate) fn new(vars_file: Option<&Path>) -> Result<Self> {
+        let mut vars = BTreeMap::new();
+        if let Some(hostname) = hostname() {
+            vars.insert("hostname".to_owned(), hostname);
+        }
+        if let Ok(username) = env::var("USER").or_else(|_| env::var("LOGNAME")) {
+            vars.insert("username".to_owned(), username);
+        }
+        vars.insert("os".to_owned(), env::consts::OS.to_owned());
+
+        if let Some(path) = vars_file {
+            let content = fs::read_to_string(path).at(path, "read variables file")?;
+            let user: BTreeMap<String, String> =
+                toml::from_str(&content).map_err(|e| ConfigError::from_toml(path, &content, e))?;
+            vars.extend(user);
+        }
+        Ok(Self(vars))
+    }
+
+    /// Render the template at `src`, replacing every `{{ name }}` with the
+    /// value of variable `name`
+    ///
+    /// Anything else between braces is kept as-is, but unknown variables are
+    /// an error.
+    pub(crate) fn render(&self, src: &Path) -> Result<String> {
+        let template = fs::read_to_string(src).at(src, "read")?;
+
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + 2 + len].trim();
+            out.push_str(&rest[..start]);
+            if is_var_name(name) {
+                let value = self.0.get(name).ok_or_else(|| Error::Template {
+                    path: src.to_owned(),
+                    message: format!("unknown variable `{name}`"),
+                })?;
+                out.push_str(value);
+            } else {
+                out.push_str(&rest[start..start + len + 4]);
+            }
+            rest = &rest[start + len + 4..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
@@ -1,8 +1,16 @@
 use std::path::Path;
+use std::sync::OnceLock;
 use std::{fs, io};
 
+use dotr::{
+    Action, Conflict, ConflictKind, EntryKind, EntryState, Error, Mode, Outcome, Resolution,
+    Resolver, StatusEntry,
+};
 use tempfile::TempDir;
 
+use crate::opts::Output;
+use crate::output::Printer;
+
 fn create_file(path: &Path) -> io::Result<()> {
     std::fs::File::create(path)?;
     Ok(())
@@ -13,6 +21,13 @@ fn assert_is_link(path: &Path, links_to: &Path) {
     assert_eq!(dst_path, links_to);
 }
 
+/// A [`super::Dotr`] keeping its state in a temporary directory, for what
+/// requires one (copies, templates, recognizing its own installs)
+fn stateful_dotr() -> super::Dotr {
+    static STATE_DIR: OnceLock<TempDir> = OnceLock::new();
+    super::Dotr::new().set_state_dir(STATE_DIR.get_or_init(|| TempDir::new().unwrap()).path())
+}
+
 fn setup() -> (TempDir, TempDir) {
     (TempDir::new().unwrap(), TempDir::new().unwrap())
 }
@@ -96,6 +111,59 @@ fn link_symlink_duplicated() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn link_and_unlink_single_entry() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    create_file(&src.join("b"))?;
+    write_dotr_config(src, "dot_prefix = \"dot_\"")?;
+    create_file(&src.join("dot_c"))?;
+
+    let report = dotr.link_entry(&src.join("d").join("a"), src, dst)?;
+    assert_eq!(report.count(Outcome::Created), 1);
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+    assert!(dst.join("b").symlink_metadata().is_err());
+    // Per the `.dotr` config
+    dotr.link_entry(&src.join("dot_c"), src, dst)?;
+    assert_is_link(&dst.join(".c"), &src.join("dot_c"));
+    // Not handled by `link`
+    assert!(
+        dotr.plan_link_entry(&src.join("d"), src, dst)?
+            .entries
+            .is_empty()
+    );
+
+    let report = dotr.unlink_entry(&src.join("d").join("a"), src, dst)?;
+    assert_eq!(report.count(Outcome::Removed), 1);
+    assert!(dst.join("d").join("a").symlink_metadata().is_err());
+    assert!(dst.join(".c").symlink_metadata().is_ok());
+    Ok(())
+}
+
+#[test]
+fn single_entry_through_symlinked_source() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (&src.path().canonicalize()?, dst.path());
+    let aliases = TempDir::new()?;
+    let alias = aliases.path().join("alias");
+    let dotr = super::Dotr::new();
+
+    std::os::unix::fs::symlink(src, &alias)?;
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+
+    let report = dotr.link_entry(&src.join("a"), &alias, dst)?;
+    assert_eq!(report.count(Outcome::Created), 1);
+    let report = dotr.link_entry(&alias.join("b"), src, dst)?;
+    assert_eq!(report.count(Outcome::Created), 1);
+    assert_is_link(&dst.join("b"), &src.join("b"));
+    Ok(())
+}
+
 // ── link: idempotency ───────────────────────────────────────────────
 
 #[test]
@@ -139,7 +207,8 @@ fn link_existing_regular_file_no_force() -> io::Result<()> {
     create_file(&src.join("a"))?;
     create_file(&dst.join("a"))?; // pre-existing regular file
 
-    dotr.link(src, dst)?; // should warn but not error
+    let report = dotr.link(src, dst)?; // should warn but not error
+    assert_eq!(report.entries[0].conflict, Some(ConflictKind::NotOurs));
 
     // dst should still be a regular file, not a symlink
     assert!(dst.join("a").symlink_metadata()?.file_type().is_file());
@@ -242,7 +311,7 @@ fn link_dst_not_exist() {
     let dotr = super::Dotr::new();
 
     let result = dotr.link(src.path(), Path::new("/tmp/dotr_nonexistent_dir"));
-    assert!(result.is_err());
+    assert!(matches!(result, Err(Error::DestinationMissing { .. })));
 }
 
 #[test]
@@ -255,7 +324,7 @@ fn link_dst_not_dir() -> io::Result<()> {
     create_file(&dst_file)?;
 
     let result = dotr.link(src.path(), &dst_file);
-    assert!(result.is_err());
+    assert!(matches!(result, Err(Error::DestinationNotDir { path }) if path == dst_file));
     Ok(())
 }
 
@@ -577,3 +646,2077 @@ fn dotr_no_config_traverses_normally() -> io::Result<()> {
     );
     Ok(())
 }
+
+// ── status ──────────────────────────────────────────────────────────
+
+fn status_of(entries: &[StatusEntry], dst: &Path) -> EntryState {
+    entries
+        .iter()
+        .find(|e| e.dst == dst)
+        .map(|e| e.state.clone())
+        .expect("entry not reported")
+}
+
+#[test]
+fn status_reports_each_state() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    create_file(&src.join("linked"))?;
+    create_file(&src.join("missing"))?;
+    create_file(&src.join("conflict"))?;
+    create_file(&src.join("elsewhere"))?;
+    std::os::unix::fs::symlink(src.join("linked"), dst.join("linked"))?;
+    create_file(&dst.join("conflict"))?;
+    create_file(&dst.join("other"))?;
+    std::os::unix::fs::symlink(dst.join("other"), dst.join("elsewhere"))?;
+
+    let entries = dotr.status(src, dst)?;
+    assert_eq!(entries.len(), 4);
+    assert_eq!(status_of(&entries, &dst.join("linked")), EntryState::Linked);
+    assert_eq!(
+        status_of(&entries, &dst.join("missing")),
+        EntryState::Missing
+    );
+    assert_eq!(
+        status_of(&entries, &dst.join("conflict")),
+        EntryState::Conflict
+    );
+    assert_eq!(
+        status_of(&entries, &dst.join("elsewhere")),
+        EntryState::PointsElsewhere(dst.join("other"))
+    );
+    Ok(())
+}
+
+#[test]
+fn status_in_sync_after_link() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("target"))?;
+    std::os::unix::fs::symlink(src.join("target"), src.join("link"))?;
+    fs::create_dir_all(src.join("subdir"))?;
+    create_file(&src.join("subdir").join("file"))?;
+    write_dotr_config(&src.join("subdir"), "traverse = \"link\"")?;
+
+    dotr.link(src, dst)?;
+
+    let entries = dotr.status(src, dst)?;
+    assert_eq!(entries.len(), 4);
+    assert!(entries.iter().all(StatusEntry::is_in_sync));
+    assert!(
+        entries
+            .iter()
+            .any(|e| e.kind == EntryKind::DirLink && e.dst.ends_with("subdir"))
+    );
+    Ok(())
+}
+
+// ── report ──────────────────────────────────────────────────────────
+
+#[test]
+fn link_report_outcomes() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    create_file(&dst.join("b"))?;
+
+    let report = dotr.link(src, dst)?;
+    assert_eq!(report.entries.len(), 2);
+    assert_eq!(report.count(Outcome::Created), 1);
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+
+    let report = dotr.link(src, dst)?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 1);
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+
+    let report = super::Dotr::new().set_force().link(src, dst)?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 0);
+    assert_eq!(report.count(Outcome::ForceReplaced), 2);
+    Ok(())
+}
+
+#[test]
+fn unlink_report_outcomes() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    create_file(&src.join("c"))?;
+    dotr.link(src, dst)?;
+    fs::remove_file(dst.join("b"))?;
+    create_file(&dst.join("b"))?;
+    fs::remove_file(dst.join("c"))?;
+
+    let report = dotr.unlink(src, dst)?;
+    let outcome_of = |name: &str| {
+        report
+            .entries
+            .iter()
+            .find(|e| e.dst.ends_with(name))
+            .unwrap()
+            .outcome
+    };
+    assert_eq!(outcome_of("a"), Outcome::Removed);
+    assert_eq!(outcome_of("b"), Outcome::ConflictSkipped);
+    assert_eq!(outcome_of("c"), Outcome::Skipped);
+    Ok(())
+}
+
+// ── plan ────────────────────────────────────────────────────────────
+
+#[test]
+fn plan_link_does_not_touch_destination() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    create_file(&src.join("d").join("b"))?;
+
+    let plan = dotr.plan_link(src, dst)?;
+    assert!(!dst.join("d").exists());
+
+    let actions: Vec<_> = plan.steps().map(|s| s.action.clone()).collect();
+    assert_eq!(
+        actions,
+        vec![
+            Action::CreateDirAll {
+                path: dst.join("d")
+            },
+            Action::CreateSymlink {
+                target: src.join("d").join("a"),
+                path: dst.join("d").join("a"),
+            },
+            Action::CreateSymlink {
+                target: src.join("d").join("b"),
+                path: dst.join("d").join("b"),
+            },
+        ]
+    );
+
+    plan.apply()?;
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+    assert_is_link(&dst.join("d").join("b"), &src.join("d").join("b"));
+    assert!(dotr.plan_link(src, dst)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn plan_link_force_replaces() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new().set_force();
+
+    create_file(&src.join("file"))?;
+    create_file(&src.join("link"))?;
+    create_file(&dst.join("file"))?;
+    std::os::unix::fs::symlink(dst.join("file"), dst.join("link"))?;
+
+    let plan = dotr.plan_link(src, dst)?;
+    let actions: Vec<_> = plan.steps().map(|s| s.action.clone()).collect();
+    assert_eq!(
+        actions,
+        vec![
+            Action::RemoveFile {
+                path: dst.join("file")
+            },
+            Action::CreateSymlink {
+                target: src.join("file"),
+                path: dst.join("file"),
+            },
+            Action::ReplaceSymlink {
+                target: src.join("link"),
+                path: dst.join("link"),
+            },
+        ]
+    );
+    Ok(())
+}
+
+// ── backup / restore ────────────────────────────────────────────────
+
+#[test]
+fn link_backup_moves_conflicts_aside() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_backup();
+
+    fs::create_dir_all(src.join("d"))?;
+    fs::create_dir_all(dst.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    fs::write(dst.join("d").join("a"), "local edits")?;
+
+    let report = dotr.link(src, dst)?;
+    assert_eq!(report.count(Outcome::BackedUp), 1);
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+
+    let backups: Vec<_> = fs::read_dir(dst.join(".dotr-backup"))?.collect();
+    assert_eq!(backups.len(), 1);
+    let backup = backups[0].as_ref().unwrap().path();
+    assert_eq!(
+        fs::read_to_string(backup.join("d").join("a"))?,
+        "local edits"
+    );
+    Ok(())
+}
+
+#[test]
+fn link_backup_keeps_correct_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    super::Dotr::new().link(src, dst)?;
+
+    let report = super::Dotr::new().set_backup().link(src, dst)?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 1);
+    assert!(!dst.join(".dotr-backup").exists());
+    Ok(())
+}
+
+#[test]
+fn restore_puts_backup_back() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_backup();
+
+    fs::create_dir_all(src.join("d"))?;
+    fs::create_dir_all(dst.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    fs::write(dst.join("d").join("a"), "local edits")?;
+    dotr.link(src, dst)?;
+
+    let report = dotr.restore(dst, None)?;
+    assert_eq!(report.count(Outcome::Restored), 1);
+    assert!(
+        dst.join("d")
+            .join("a")
+            .symlink_metadata()?
+            .file_type()
+            .is_file()
+    );
+    assert_eq!(fs::read_to_string(dst.join("d").join("a"))?, "local edits");
+    // the emptied backup is cleaned up
+    assert_eq!(fs::read_dir(dst.join(".dotr-backup"))?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn restore_without_backups_fails() {
+    let dst = TempDir::new().unwrap();
+    assert!(super::Dotr::new().restore(dst.path(), None).is_err());
+}
+
+// ── adopt ───────────────────────────────────────────────────────────
+
+#[test]
+fn adopt_file_moves_and_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(dst.join("d"))?;
+    fs::write(dst.join("d").join("a"), "content")?;
+
+    let report = dotr.adopt(src, dst, &[dst.join("d").join("a")])?;
+    assert_eq!(report.count(Outcome::Adopted), 1);
+    assert_eq!(fs::read_to_string(src.join("d").join("a"))?, "content");
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+
+    // adopting again is a no-op
+    let report = dotr.adopt(src, dst, &[dst.join("d").join("a")])?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 1);
+    Ok(())
+}
+
+#[test]
+fn adopt_identical_source_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::write(src.join("a"), "same")?;
+    fs::write(dst.join("a"), "same")?;
+
+    dotr.adopt(src, dst, &[dst.join("a")])?;
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    Ok(())
+}
+
+#[test]
+fn adopt_refuses_different_source() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::write(src.join("a"), "repo")?;
+    fs::write(dst.join("a"), "local")?;
+    create_file(&dst.join("b"))?;
+
+    let err = dotr
+        .adopt(src, dst, &[dst.join("b"), dst.join("a")])
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Conflict { src: conflict_src, dst: conflict_dst, kind: ConflictKind::SourceDiffers }
+            if conflict_src == src.join("a") && conflict_dst == dst.join("a")
+    ));
+    // nothing was touched
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "local");
+    assert!(dst.join("b").symlink_metadata()?.file_type().is_file());
+    assert!(!src.join("b").exists());
+    Ok(())
+}
+
+#[test]
+fn adopt_dir_links_files_individually() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(dst.join("d").join("e"))?;
+    create_file(&dst.join("d").join("a"))?;
+    create_file(&dst.join("d").join("e").join("b"))?;
+
+    dotr.adopt(src, dst, &[dst.join("d")])?;
+    assert!(dst.join("d").symlink_metadata()?.is_dir());
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+    assert_is_link(
+        &dst.join("d").join("e").join("b"),
+        &src.join("d").join("e").join("b"),
+    );
+    assert!(src.join("d").join("e").join("b").is_file());
+    Ok(())
+}
+
+#[test]
+fn adopt_dir_with_traverse_link() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+    fs::create_dir_all(dst.join("d").join("e"))?;
+    create_file(&dst.join("d").join("e").join("b"))?;
+
+    dotr.adopt(src, dst, &[dst.join("d")])?;
+    assert_is_link(&dst.join("d"), &src.join("d"));
+    assert!(src.join("d").join("e").join("b").is_file());
+    Ok(())
+}
+
+#[test]
+fn adopt_dir_with_traverse_link_and_symlinks() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+    create_file(&src.join("d").join("linked"))?;
+    fs::create_dir_all(dst.join("d"))?;
+    create_file(&dst.join("d").join("file"))?;
+    std::os::unix::fs::symlink("/etc/hostname", dst.join("d").join("lnk"))?;
+    // Already pointing to the source
+    std::os::unix::fs::symlink(src.join("d").join("linked"), dst.join("d").join("linked"))?;
+
+    dotr.adopt(src, dst, &[dst.join("d")])?;
+    assert_is_link(&dst.join("d"), &src.join("d"));
+    assert!(src.join("d").join("file").is_file());
+    assert_is_link(&src.join("d").join("lnk"), Path::new("/etc/hostname"));
+    assert!(src.join("d").join("linked").is_file());
+    Ok(())
+}
+
+#[test]
+fn adopt_outside_destination_fails() -> io::Result<()> {
+    let (src, dst) = setup();
+    let other = TempDir::new().unwrap();
+    create_file(&other.path().join("a"))?;
+
+    let result = super::Dotr::new().adopt(src.path(), dst.path(), &[other.path().join("a")]);
+    assert!(result.is_err());
+    Ok(())
+}
+
+// ── exclusions ──────────────────────────────────────────────────────
+
+#[test]
+fn dotrignore_excludes_matching_paths() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::write(src.join(".dotrignore"), "README.md\n.github/\n*.nix\n")?;
+    create_file(&src.join("README.md"))?;
+    create_file(&src.join("flake.nix"))?;
+    fs::create_dir_all(src.join(".github"))?;
+    create_file(&src.join(".github").join("ci.yml"))?;
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("README.md"))?;
+    create_file(&src.join("a"))?;
+
+    dotr.link(src, dst)?;
+
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert!(!dst.join(".dotrignore").exists());
+    assert!(!dst.join("README.md").exists());
+    assert!(!dst.join("flake.nix").exists());
+    assert!(!dst.join(".github").exists());
+    // unanchored patterns apply in subdirectories too
+    assert!(!dst.join("d").join("README.md").exists());
+
+    let entries = dotr.status(src, dst)?;
+    assert_eq!(entries.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn dotrignore_in_subdirectory() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::write(src.join(".dotrignore"), "*.md\n")?;
+    fs::create_dir_all(src.join("d"))?;
+    fs::write(src.join("d").join(".dotrignore"), "skip\n!keep.md\n")?;
+    create_file(&src.join("d").join("skip"))?;
+    create_file(&src.join("d").join("keep.md"))?;
+    create_file(&src.join("d").join("other.md"))?;
+    create_file(&src.join("skip"))?;
+
+    dotr.link(src, dst)?;
+
+    assert!(!dst.join("d").join("skip").exists());
+    assert!(!dst.join("d").join("other.md").exists());
+    assert_is_link(
+        &dst.join("d").join("keep.md"),
+        &src.join("d").join("keep.md"),
+    );
+    // the subdirectory's patterns don't apply outside of it
+    assert_is_link(&dst.join("skip"), &src.join("skip"));
+    Ok(())
+}
+
+#[test]
+fn exclude_patterns() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("LICENSE"))?;
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("b"))?;
+
+    super::Dotr::new().link(src, dst)?;
+    super::Dotr::new()
+        .add_exclude("LICENSE")
+        .add_exclude("/d")
+        .unlink(src, dst)?;
+
+    assert!(dst.join("a").symlink_metadata().is_err());
+    assert_is_link(&dst.join("LICENSE"), &src.join("LICENSE"));
+    assert_is_link(&dst.join("d").join("b"), &src.join("d").join("b"));
+    Ok(())
+}
+
+// ── .dotr config: ignore / target ──────────────────────────────────
+
+#[test]
+fn dotr_ignore_skips_entries() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d").join("cache"))?;
+    write_dotr_config(&src.join("d"), "ignore = [\"notes.txt\", \"cache/\"]")?;
+    create_file(&src.join("d").join("notes.txt"))?;
+    create_file(&src.join("d").join("cache").join("x"))?;
+    create_file(&src.join("d").join("a"))?;
+    create_file(&src.join("notes.txt"))?;
+
+    dotr.link(src, dst)?;
+
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+    assert!(!dst.join("d").join("notes.txt").exists());
+    assert!(!dst.join("d").join("cache").exists());
+    // only applies within the directory
+    assert_is_link(&dst.join("notes.txt"), &src.join("notes.txt"));
+    Ok(())
+}
+
+#[test]
+fn dotr_target_relative() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("nvim").join("lua"))?;
+    write_dotr_config(&src.join("nvim"), "target = \".config/nvim\"")?;
+    create_file(&src.join("nvim").join("init.lua"))?;
+    create_file(&src.join("nvim").join("lua").join("a.lua"))?;
+
+    dotr.link(src, dst)?;
+
+    assert!(!dst.join("nvim").exists());
+    assert_is_link(
+        &dst.join(".config").join("nvim").join("init.lua"),
+        &src.join("nvim").join("init.lua"),
+    );
+    assert_is_link(
+        &dst.join(".config").join("nvim").join("lua").join("a.lua"),
+        &src.join("nvim").join("lua").join("a.lua"),
+    );
+    assert!(dotr.status(src, dst)?.iter().all(StatusEntry::is_in_sync));
+
+    dotr.unlink(src, dst)?;
+    assert!(
+        dst.join(".config")
+            .join("nvim")
+            .join("init.lua")
+            .symlink_metadata()
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn dotr_target_absolute_with_traverse_link() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let elsewhere = TempDir::new().unwrap();
+    let target = elsewhere.path().join("nvim");
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("nvim"))?;
+    write_dotr_config(
+        &src.join("nvim"),
+        &format!(
+            "traverse = \"link\"\ntarget = {:?}",
+            target.display().to_string()
+        ),
+    )?;
+    create_file(&src.join("nvim").join("init.lua"))?;
+
+    dotr.link(src, dst)?;
+    assert_is_link(&target, &src.join("nvim"));
+
+    dotr.unlink(src, dst)?;
+    assert!(target.symlink_metadata().is_err());
+    Ok(())
+}
+
+// ── .dotr config: errors ────────────────────────────────────────────
+
+#[test]
+fn dotr_invalid_value_is_fatal() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("subdir"))?;
+    create_file(&src.join("subdir").join("file"))?;
+    write_dotr_config(&src.join("subdir"), "traverse = \"links\"")?;
+
+    let err = dotr.link(src, dst).unwrap_err();
+    assert!(matches!(err, Error::ConfigParse(e) if e.path.ends_with("subdir/.dotr")));
+    assert!(!dst.join("subdir").exists());
+    assert!(dotr.status(src, dst).is_err());
+    Ok(())
+}
+
+#[test]
+fn dotr_unknown_key_is_fatal() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    fs::create_dir_all(src.join("subdir"))?;
+    write_dotr_config(
+        &src.join("subdir"),
+        "traverse = \"link\"\ntraverze = \"link\"",
+    )?;
+
+    assert!(super::Dotr::new().link(src, dst).is_err());
+    Ok(())
+}
+
+#[test]
+fn check_reports_every_invalid_config() -> io::Result<()> {
+    let src = TempDir::new().unwrap();
+    let src = src.path();
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("a"))?;
+    fs::create_dir_all(src.join("b"))?;
+    fs::create_dir_all(src.join("c"))?;
+    write_dotr_config(src, "traverse = \"link\"")?;
+    write_dotr_config(&src.join("a"), "traverse = \"links\"")?;
+    write_dotr_config(&src.join("b"), "\n\nnot toml")?;
+    write_dotr_config(&src.join("c"), "ignore = [\"*.md\"]")?;
+
+    let errors = dotr.check(src)?;
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].path.ends_with("a/.dotr"));
+    assert!(errors[1].path.ends_with("b/.dotr"));
+    assert_eq!(errors[1].line_col.map(|(line, _)| line), Some(2));
+
+    fs::remove_file(src.join("a").join(".dotr"))?;
+    fs::remove_file(src.join("b").join(".dotr"))?;
+    assert!(dotr.check(src)?.is_empty());
+    Ok(())
+}
+
+// ── .dotr config: dot_prefix ───────────────────────────────────────
+
+#[test]
+fn dot_prefix_translates_names() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new();
+
+    write_dotr_config(src, "dot_prefix = \"dot_\"")?;
+    fs::create_dir_all(src.join("dot_config").join("dot_nvim"))?;
+    create_file(&src.join("dot_bashrc"))?;
+    create_file(&src.join("dot_config").join("dot_nvim").join("init.lua"))?;
+    create_file(&src.join("plain"))?;
+
+    dotr.link(src, dst)?;
+
+    assert_is_link(&dst.join(".bashrc"), &src.join("dot_bashrc"));
+    assert_is_link(
+        &dst.join(".config").join(".nvim").join("init.lua"),
+        &src.join("dot_config").join("dot_nvim").join("init.lua"),
+    );
+    assert_is_link(&dst.join("plain"), &src.join("plain"));
+    assert!(!dst.join("dot_bashrc").exists());
+    assert!(dotr.status(src, dst)?.iter().all(StatusEntry::is_in_sync));
+
+    dotr.unlink(src, dst)?;
+    assert!(dst.join(".bashrc").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn dot_prefix_disabled_by_default() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("dot_bashrc"))?;
+    super::Dotr::new().link(src, dst)?;
+
+    assert_is_link(&dst.join("dot_bashrc"), &src.join("dot_bashrc"));
+    Ok(())
+}
+
+#[test]
+fn dot_prefix_adopt() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let (src, dst) = (src.as_path(), dst.as_path());
+    let dotr = super::Dotr::new();
+
+    write_dotr_config(src, "dot_prefix = \"dot_\"")?;
+    fs::create_dir_all(dst.join(".config"))?;
+    create_file(&dst.join(".config").join("app"))?;
+
+    dotr.adopt(src, dst, &[dst.join(".config")])?;
+
+    assert!(src.join("dot_config").join("app").is_file());
+    assert_is_link(
+        &dst.join(".config").join("app"),
+        &src.join("dot_config").join("app"),
+    );
+    Ok(())
+}
+
+// ── copy mode ───────────────────────────────────────────────────────
+
+#[test]
+fn copy_mode_installs_copies() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new()
+        .set_mode(Mode::Copy)
+        .set_state_dir(state.path());
+
+    fs::write(src.join("a"), "content")?;
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Created), 1);
+    assert!(dst.join("a").symlink_metadata()?.file_type().is_file());
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "content");
+
+    let status = dotr.status(src, dst)?;
+    assert_eq!(status[0].kind, EntryKind::Copy);
+    assert_eq!(status[0].state, EntryState::Copied);
+    assert!(status[0].is_in_sync());
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::AlreadyCorrect), 1);
+
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::Removed), 1);
+    assert!(!dst.join("a").exists());
+    Ok(())
+}
+
+#[test]
+fn copy_mode_keeps_modified_copies() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new()
+        .set_mode(Mode::Copy)
+        .set_state_dir(state.path());
+
+    fs::write(src.join("a"), "content")?;
+    dotr.link(src, dst)?;
+    fs::write(dst.join("a"), "local edits")?;
+
+    assert_eq!(
+        status_of(&dotr.status(src, dst)?, &dst.join("a")),
+        EntryState::Drifted
+    );
+    let report = dotr.link(src, dst)?;
+    assert_eq!(report.entries[0].conflict, Some(ConflictKind::Modified));
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::ConflictSkipped), 1);
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "local edits");
+    Ok(())
+}
+
+#[test]
+fn copy_mode_updates_after_source_change() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new().set_state_dir(state.path());
+
+    write_dotr_config(src, "[files.\"a\"]\nmode = \"copy\"")?;
+    fs::write(src.join("a"), "v1")?;
+    create_file(&src.join("b"))?;
+    dotr.link(src, dst)?;
+    assert_is_link(&dst.join("b"), &src.join("b"));
+
+    fs::write(src.join("a"), "v2")?;
+    assert_eq!(
+        status_of(&dotr.status(src, dst)?, &dst.join("a")),
+        EntryState::Drifted
+    );
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Updated), 1);
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "v2");
+    Ok(())
+}
+
+#[test]
+fn copy_mode_switches_from_symlinks() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new().set_state_dir(state.path());
+
+    fs::create_dir_all(src.join("d"))?;
+    fs::write(src.join("d").join("a"), "content")?;
+    dotr.link(src, dst)?;
+    assert_is_link(&dst.join("d").join("a"), &src.join("d").join("a"));
+
+    write_dotr_config(&src.join("d"), "mode = \"copy\"")?;
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Updated), 1);
+    let copy = dst.join("d").join("a");
+    assert!(copy.symlink_metadata()?.file_type().is_file());
+    assert_eq!(fs::read_to_string(src.join("d").join("a"))?, "content");
+
+    // And back
+    fs::remove_file(src.join("d").join(".dotr"))?;
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Updated), 1);
+    assert_is_link(&copy, &src.join("d").join("a"));
+    Ok(())
+}
+
+// ── hardlink mode ───────────────────────────────────────────────────
+
+fn is_hard_link(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (a.symlink_metadata()?, b.symlink_metadata()?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[test]
+fn hardlink_mode_links_and_unlinks() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_mode(Mode::Hardlink);
+
+    create_file(&src.join("a"))?;
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Created), 1);
+    assert!(is_hard_link(&src.join("a"), &dst.join("a"))?);
+    assert_eq!(
+        status_of(&dotr.status(src, dst)?, &dst.join("a")),
+        EntryState::Hardlinked
+    );
+    assert!(dotr.status(src, dst)?.iter().all(StatusEntry::is_in_sync));
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::AlreadyCorrect), 1);
+
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::Removed), 1);
+    assert!(!dst.join("a").exists());
+    assert!(src.join("a").exists());
+    Ok(())
+}
+
+#[test]
+fn hardlink_mode_unlink_keeps_other_files() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_mode(Mode::Hardlink);
+
+    write_dotr_config(src, "mode = \"hardlink\"")?;
+    create_file(&src.join("a"))?;
+    super::Dotr::new().link(src, dst)?;
+    fs::remove_file(dst.join("a"))?;
+    create_file(&dst.join("a"))?;
+
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::ConflictSkipped), 1);
+    assert!(dst.join("a").exists());
+    Ok(())
+}
+
+#[test]
+fn hardlink_mode_replaces_symlinks() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    super::Dotr::new().link(src, dst)?;
+
+    let report = super::Dotr::new().set_mode(Mode::Hardlink).link(src, dst)?;
+    assert_eq!(report.count(Outcome::Updated), 1);
+    assert!(is_hard_link(&src.join("a"), &dst.join("a"))?);
+    Ok(())
+}
+
+// ── templates ───────────────────────────────────────────────────────
+
+#[test]
+fn template_rendered_without_suffix() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let vars = state.path().join("vars.toml");
+    fs::write(&vars, "email = \"me@work\"")?;
+    let dotr = super::Dotr::new()
+        .set_state_dir(state.path())
+        .set_vars_file(&vars);
+
+    fs::write(
+        src.join("gitconfig.tmpl"),
+        "email = {{ email }}\nos = {{os}}\n${{ not a variable }}",
+    )?;
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Created), 1);
+    assert!(!dst.join("gitconfig.tmpl").exists());
+    assert_eq!(
+        fs::read_to_string(dst.join("gitconfig"))?,
+        format!(
+            "email = me@work\nos = {}\n${{{{ not a variable }}}}",
+            std::env::consts::OS
+        )
+    );
+    let status = dotr.status(src, dst)?;
+    assert_eq!(status[0].kind, EntryKind::Template);
+    assert!(status[0].is_in_sync());
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::AlreadyCorrect), 1);
+
+    fs::write(&vars, "email = \"me@home\"")?;
+    assert_eq!(
+        status_of(&dotr.status(src, dst)?, &dst.join("gitconfig")),
+        EntryState::Drifted
+    );
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::Updated), 1);
+    assert!(fs::read_to_string(dst.join("gitconfig"))?.starts_with("email = me@home\n"));
+
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::Removed), 1);
+    assert!(!dst.join("gitconfig").exists());
+    Ok(())
+}
+
+#[test]
+fn template_modified_render_kept() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new().set_state_dir(state.path());
+
+    write_dotr_config(src, "[files.\"profile\"]\nmode = \"template\"")?;
+    fs::write(src.join("profile"), "export OS={{ os }}")?;
+    dotr.link(src, dst)?;
+    fs::write(dst.join("profile"), "local edits")?;
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::ConflictSkipped), 1);
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::ConflictSkipped), 1);
+    assert_eq!(fs::read_to_string(dst.join("profile"))?, "local edits");
+    Ok(())
+}
+
+#[test]
+fn template_unknown_variable_fails() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let state = TempDir::new()?;
+    let dotr = super::Dotr::new().set_state_dir(state.path());
+
+    fs::write(src.join("a.tmpl"), "{{ nope }}")?;
+
+    let err = dotr.link(src, dst).unwrap_err();
+    assert!(matches!(&err, Error::Template { path, .. } if path.ends_with("a.tmpl")));
+    assert!(err.to_string().contains("unknown variable `nope`"));
+    assert!(!dst.join("a").exists());
+    Ok(())
+}
+
+// ── variants ────────────────────────────────────────────────────────
+
+#[test]
+fn variants_pick_best_match() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("gitconfig"))?;
+    create_file(&src.join("gitconfig##profile.work"))?;
+    create_file(&src.join("gitconfig##host.no-such-host"))?;
+    create_file(&src.join("env##profile.work"))?;
+    create_file(&src.join("env##profile.work,profile.laptop"))?;
+    create_file(&src.join("only##profile.home"))?;
+
+    let dotr = super::Dotr::new().add_profile("work").add_profile("laptop");
+    dotr.link(src, dst)?;
+    assert_is_link(&dst.join("gitconfig"), &src.join("gitconfig##profile.work"));
+    assert_is_link(
+        &dst.join("env"),
+        &src.join("env##profile.work,profile.laptop"),
+    );
+    assert!(dst.join("only").symlink_metadata().is_err());
+    assert!(!dst.join("gitconfig##profile.work").exists());
+
+    let status = dotr.status(src, dst)?;
+    assert_eq!(status.len(), 2);
+    assert!(status.iter().all(StatusEntry::is_in_sync));
+    assert_eq!(dotr.unlink(src, dst)?.count(Outcome::Removed), 2);
+
+    let dotr = super::Dotr::new();
+    dotr.link(src, dst)?;
+    assert_is_link(&dst.join("gitconfig"), &src.join("gitconfig"));
+    assert!(dst.join("env").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn dotr_profiles_restrict_directory() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    fs::create_dir_all(src.join("work"))?;
+    write_dotr_config(&src.join("work"), "profiles = [\"work\"]")?;
+    create_file(&src.join("work").join("a"))?;
+    fs::create_dir_all(src.join("elsewhere"))?;
+    write_dotr_config(&src.join("elsewhere"), "hosts = [\"no-such-host\"]")?;
+    create_file(&src.join("elsewhere").join("b"))?;
+
+    super::Dotr::new().link(src, dst)?;
+    assert!(!dst.join("work").exists());
+    assert!(!dst.join("elsewhere").exists());
+
+    super::Dotr::new().add_profile("work").link(src, dst)?;
+    assert_is_link(&dst.join("work").join("a"), &src.join("work").join("a"));
+    assert!(!dst.join("elsewhere").exists());
+    Ok(())
+}
+
+// ── layers ──────────────────────────────────────────────────────────
+
+#[test]
+fn layers_later_wins() -> io::Result<()> {
+    let (company, personal) = setup();
+    let (company, personal) = (company.path(), personal.path());
+    let dst = TempDir::new()?;
+    let dst = dst.path();
+    let dotr = super::Dotr::new();
+
+    create_file(&company.join("a"))?;
+    create_file(&company.join("b"))?;
+    create_file(&personal.join("b"))?;
+
+    let layers = [company, personal];
+    let report = dotr.link_layers(&layers, dst)?;
+    assert_eq!(report.count(Outcome::Created), 2);
+    assert_eq!(report.count(Outcome::Shadowed), 1);
+    assert_is_link(&dst.join("a"), &company.join("a"));
+    assert_is_link(&dst.join("b"), &personal.join("b"));
+
+    let status = dotr.status_layers(&layers, dst)?;
+    assert_eq!(status.len(), 2);
+    assert!(status.iter().all(StatusEntry::is_in_sync));
+
+    assert_eq!(dotr.unlink_layers(&layers, dst)?.count(Outcome::Removed), 2);
+    assert!(dst.join("b").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn layers_shadow_linked_dirs() -> io::Result<()> {
+    let (company, personal) = setup();
+    let (company, personal) = (company.path(), personal.path());
+    let dst = TempDir::new()?;
+    let dst = dst.path();
+
+    fs::create_dir_all(company.join("nvim"))?;
+    write_dotr_config(&company.join("nvim"), "traverse = \"link\"")?;
+    create_file(&company.join("nvim").join("init.lua"))?;
+    fs::create_dir_all(personal.join("nvim"))?;
+    create_file(&personal.join("nvim").join("init.lua"))?;
+
+    let plan = super::Dotr::new().plan_link_layers(&[company, personal], dst)?;
+    let shadowed: Vec<_> = plan
+        .entries
+        .iter()
+        .filter(|e| e.outcome == Outcome::Shadowed)
+        .collect();
+    assert_eq!(shadowed.len(), 1);
+    assert_eq!(shadowed[0].src, company.canonicalize()?.join("nvim"));
+
+    plan.apply()?;
+    assert!(
+        !dst.join("nvim")
+            .symlink_metadata()?
+            .file_type()
+            .is_symlink()
+    );
+    assert_is_link(
+        &dst.join("nvim").join("init.lua"),
+        &personal.canonicalize()?.join("nvim").join("init.lua"),
+    );
+    Ok(())
+}
+
+// ── config file ─────────────────────────────────────────────────────
+
+#[test]
+fn config_file_provides_defaults() -> io::Result<()> {
+    use clap::Parser;
+
+    use super::opts::{ConfigFile, Options};
+
+    let dir = TempDir::new()?;
+    let path = dir.path().join("config.toml");
+    fs::write(
+        &path,
+        "dst_dir = \"/home/me\"\nsrc_dirs = [\"/shared\", \"/mine\"]\nbackup = true\nrelative = true\nexclude = [\"*.md\"]\nmode = \"copy\"",
+    )?;
+    let config = || ConfigFile::load(&path, true).unwrap();
+
+    let opts = Options::parse_from(["dotr", "link"]).with_config(config());
+    assert_eq!(opts.dst_dir, Some("/home/me".into()));
+    assert_eq!(opts.src_dir, vec![Path::new("/shared"), Path::new("/mine")]);
+    assert!(opts.backup);
+    assert!(opts.relative);
+    assert!(!opts.force);
+    assert_eq!(opts.exclude, vec!["*.md"]);
+    assert_eq!(opts.mode, Some(Mode::Copy));
+
+    let opts = Options::parse_from([
+        "dotr",
+        "--dst-dir",
+        "/other",
+        "--src-dir",
+        "/src",
+        "--exclude",
+        "LICENSE",
+        "--mode",
+        "symlink",
+        "link",
+    ])
+    .with_config(config());
+    assert_eq!(opts.dst_dir, Some("/other".into()));
+    assert_eq!(opts.src_dir, vec![Path::new("/src")]);
+    assert_eq!(opts.exclude, vec!["*.md", "LICENSE"]);
+    assert_eq!(opts.mode, Some(Mode::Symlink));
+
+    let opts = Options::parse_from(["dotr", "--interactive", "link"]).with_config(config());
+    assert!(!opts.backup);
+
+    let opts =
+        Options::parse_from(["dotr", "--no-backup", "--no-relative", "link"]).with_config(config());
+    assert!(!opts.backup);
+    assert!(!opts.relative);
+
+    let opts = Options::parse_from(["dotr", "--force", "link"]).with_config(config());
+    assert!(opts.force);
+    assert!(!opts.backup);
+
+    let opts =
+        Options::parse_from(["dotr", "--relative", "--no-relative", "link"]).with_config(config());
+    assert!(!opts.relative);
+    Ok(())
+}
+
+#[test]
+fn config_file_missing_or_invalid() -> io::Result<()> {
+    use super::opts::ConfigFile;
+
+    let dir = TempDir::new()?;
+    let path = dir.path().join("config.toml");
+    assert!(ConfigFile::load(&path, false).is_ok());
+    assert!(ConfigFile::load(&path, true).is_err());
+
+    fs::write(&path, "dst-dir = \"/home/me\"")?;
+    assert!(ConfigFile::load(&path, false).is_err());
+    Ok(())
+}
+
+// ── prune ───────────────────────────────────────────────────────────
+
+#[test]
+fn prune_removes_stale_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let other = TempDir::new()?;
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("a"))?;
+    create_file(&src.join("d").join("b"))?;
+    dotr.link(src, dst)?;
+    fs::remove_file(src.join("d").join("b"))?;
+    // Dangling, but not into the source
+    std::os::unix::fs::symlink(other.path().join("gone"), dst.join("unrelated"))?;
+
+    let plan = dotr.plan_prune(&[src], dst, 5)?;
+    assert_eq!(plan.entries.len(), 1);
+    assert!(dst.join("d").join("b").symlink_metadata().is_ok());
+
+    assert_eq!(dotr.prune(&[src], dst, 5)?.count(Outcome::Removed), 1);
+    assert!(dst.join("d").join("b").symlink_metadata().is_err());
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert!(dst.join("unrelated").symlink_metadata().is_ok());
+    Ok(())
+}
+
+#[test]
+fn prune_respects_max_depth() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    // Recorded links are pruned regardless of depth
+    let dotr = super::Dotr::new();
+
+    let deep = Path::new("a").join("b").join("c");
+    fs::create_dir_all(src.join(&deep))?;
+    create_file(&src.join(&deep).join("f"))?;
+    dotr.link(src, dst)?;
+    fs::remove_file(src.join(&deep).join("f"))?;
+
+    assert!(dotr.plan_prune(&[src], dst, 3)?.is_empty());
+    assert_eq!(dotr.prune(&[src], dst, 4)?.count(Outcome::Removed), 1);
+    Ok(())
+}
+
+#[test]
+fn prune_skips_source_inside_destination() -> io::Result<()> {
+    let dst = TempDir::new()?;
+    let dst = dst.path().canonicalize()?;
+    let src = dst.join("dotfiles");
+    let dotr = super::Dotr::new();
+
+    fs::create_dir_all(src.join(".git"))?;
+    create_file(&src.join("a"))?;
+    std::os::unix::fs::symlink("missing-target", src.join("dangling"))?;
+    std::os::unix::fs::symlink(src.join("gone"), src.join(".git").join("dangling"))?;
+    dotr.link(&src, &dst)?;
+
+    assert_eq!(dotr.prune(&[&src], &dst, 5)?.count(Outcome::Removed), 0);
+    assert!(src.join("dangling").symlink_metadata().is_ok());
+    assert!(src.join(".git").join("dangling").symlink_metadata().is_ok());
+    Ok(())
+}
+
+// ── state ───────────────────────────────────────────────────────────
+
+#[test]
+fn state_unlinks_renamed_sources() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = stateful_dotr();
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    dotr.link(src, dst)?;
+    fs::rename(src.join("a"), src.join("c"))?;
+
+    assert_eq!(
+        status_of(&dotr.status(src, dst)?, &dst.join("a")),
+        EntryState::Stale
+    );
+    let report = dotr.unlink(src, dst)?;
+    assert_eq!(report.count(Outcome::Removed), 2);
+    assert!(dst.join("a").symlink_metadata().is_err());
+    assert!(dst.join("b").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn state_replaces_own_links_without_force() -> io::Result<()> {
+    let (company, personal) = setup();
+    let (company, personal) = (company.path(), personal.path());
+    let dst = TempDir::new()?;
+    let dst = dst.path();
+    let dotr = stateful_dotr();
+
+    create_file(&company.join("a"))?;
+    create_file(&personal.join("a"))?;
+    dotr.link(company, dst)?;
+
+    let report = dotr.link_layers(&[company, personal], dst)?;
+    assert_eq!(report.count(Outcome::Updated), 1);
+    assert_is_link(&dst.join("a"), &personal.canonicalize()?.join("a"));
+
+    // Not recorded, so not known to be ours
+    let dotr = super::Dotr::new();
+    let report = dotr.link_layers(&[personal, company], dst)?;
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    Ok(())
+}
+
+#[test]
+fn state_leaves_other_sources_alone() -> io::Result<()> {
+    let (company, personal) = setup();
+    let (company, personal) = (company.path(), personal.path());
+    let dst = TempDir::new()?;
+    let dst = dst.path();
+    let dotr = stateful_dotr();
+
+    create_file(&company.join("x"))?;
+    create_file(&personal.join("x"))?;
+    dotr.link(company, dst)?;
+
+    let report = dotr.unlink(personal, dst)?;
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    let report = dotr.link(personal, dst)?;
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    assert_is_link(&dst.join("x"), &company.join("x"));
+    Ok(())
+}
+
+#[test]
+fn state_is_opt_in() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b.tmpl"))?;
+    let report = super::Dotr::new().set_mode(Mode::Copy).link(src, dst)?;
+    assert_eq!(report.count(Outcome::Skipped), 2);
+    assert!(report.entries[0].error.as_ref().unwrap().contains("copies"));
+    assert!(
+        report.entries[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("templates")
+    );
+    assert!(dst.join("a").symlink_metadata().is_err());
+
+    // Not in the way of the rest
+    create_file(&src.join("c"))?;
+    let report = super::Dotr::new().link(src, dst)?;
+    assert_eq!(report.count(Outcome::Created), 2);
+    assert_eq!(report.count(Outcome::Skipped), 1);
+    Ok(())
+}
+
+#[test]
+fn state_prunes_stale_copies() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = stateful_dotr().set_mode(Mode::Copy);
+
+    fs::write(src.join("a"), "a")?;
+    fs::write(src.join("b"), "b")?;
+    dotr.link(src, dst)?;
+    fs::remove_file(src.join("a"))?;
+    fs::remove_file(src.join("b"))?;
+    fs::write(dst.join("b"), "local edits")?;
+
+    let report = dotr.prune(&[src], dst, 5)?;
+    assert_eq!(report.count(Outcome::Removed), 1);
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    assert!(!dst.join("a").exists());
+    assert_eq!(fs::read_to_string(dst.join("b"))?, "local edits");
+    Ok(())
+}
+
+#[test]
+fn state_survives_partial_failure() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = stateful_dotr();
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    let plan = dotr.plan_link(src, dst)?;
+    create_file(&dst.join("b"))?;
+    assert!(plan.apply().is_err());
+
+    fs::remove_file(src.join("a"))?;
+    assert_eq!(dotr.prune(&[src], dst, 0)?.count(Outcome::Removed), 1);
+    assert!(dst.join("a").symlink_metadata().is_err());
+    Ok(())
+}
+
+// ── keep going ──────────────────────────────────────────────────────
+
+#[test]
+fn keep_going_past_planning_errors() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    fs::write(src.join("b.tmpl"), "{{ no_such_variable }}")?;
+    create_file(&src.join("c"))?;
+
+    assert!(stateful_dotr().link(src, dst).is_err());
+    assert!(dst.join("a").symlink_metadata().is_err());
+
+    let Err(Error::Failures(failures)) = stateful_dotr().set_keep_going().link(src, dst) else {
+        panic!("expected failures");
+    };
+    assert_eq!(failures.report.count(Outcome::Created), 2);
+    assert_eq!(failures.report.count(Outcome::Failed), 1);
+    assert!(failures.to_string().contains("no_such_variable"));
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert_is_link(&dst.join("c"), &src.join("c"));
+    Ok(())
+}
+
+#[test]
+fn keep_going_past_directory_errors() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = stateful_dotr();
+
+    create_file(&src.join("a"))?;
+    fs::create_dir(src.join("d"))?;
+    create_file(&src.join("d").join("b"))?;
+    create_file(&src.join("e"))?;
+    dotr.link(src, dst)?;
+    fs::write(src.join("d").join(".dotr"), "mode =")?;
+
+    assert!(matches!(
+        dotr.plan_unlink(src, dst),
+        Err(Error::ConfigParse(_))
+    ));
+
+    let dotr = dotr.set_keep_going();
+    let plan = dotr.plan_link(src, dst)?;
+    assert_eq!(plan.entries.len(), 3);
+    let Err(Error::Failures(failures)) = dotr.unlink(src, dst) else {
+        panic!("expected failures");
+    };
+    let failed = &failures.report.entries[1];
+    assert_eq!(
+        (failed.outcome, failed.dst.as_path()),
+        (Outcome::Failed, &*dst.join("d"))
+    );
+    assert_eq!(failures.report.count(Outcome::Removed), 2);
+    // Nothing known about the subtree, so it's left alone
+    assert_is_link(&dst.join("d").join("b"), &src.join("d").join("b"));
+    Ok(())
+}
+
+#[test]
+fn keep_going_past_apply_errors() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    let plan = super::Dotr::new().set_keep_going().plan_link(src, dst)?;
+    // Appears after planning, so creating the link fails
+    create_file(&dst.join("a"))?;
+
+    let Err(Error::Failures(failures)) = plan.apply() else {
+        panic!("expected failures");
+    };
+    assert_eq!(failures.report.entries[0].outcome, Outcome::Failed);
+    assert_eq!(failures.report.entries[1].outcome, Outcome::Created);
+    assert_is_link(&dst.join("b"), &src.join("b"));
+    Ok(())
+}
+
+// ── atomic ──────────────────────────────────────────────────────────
+
+#[test]
+fn atomic_rolls_back_on_failure() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_force().set_atomic();
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("a"))?;
+    create_file(&src.join("d").join("b"))?;
+    create_file(&src.join("e"))?;
+    fs::write(dst.join("a"), "mine")?;
+    let plan = dotr.plan_link(src, dst)?;
+    // Appears after planning, so creating the last link fails
+    create_file(&dst.join("e"))?;
+
+    let err = plan.apply().unwrap_err();
+    assert!(matches!(err, Error::RolledBack { complete: true, .. }));
+    assert!(err.to_string().contains("rolled back"));
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "mine");
+    assert!(dst.join("d").symlink_metadata().is_err());
+    assert!(dst.join("a.dotr-stash").symlink_metadata().is_err());
+
+    fs::remove_file(dst.join("e"))?;
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::ForceReplaced), 1);
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert!(dst.join("a.dotr-stash").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn atomic_keeps_existing_stash_names() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = stateful_dotr().set_force().set_atomic();
+
+    create_file(&src.join("a"))?;
+    fs::write(dst.join("a"), "mine")?;
+    fs::write(dst.join("a.dotr-stash"), "precious")?;
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::ForceReplaced), 1);
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert_eq!(fs::read_to_string(dst.join("a.dotr-stash"))?, "precious");
+    assert!(dst.join("a.dotr-stash.1").symlink_metadata().is_err());
+
+    // Nor temporary files
+    fs::write(src.join("b"), "copied")?;
+    fs::write(dst.join("b.dotr-tmp"), "precious")?;
+    stateful_dotr().set_mode(Mode::Copy).link(src, dst)?;
+    assert_eq!(fs::read_to_string(dst.join("b"))?, "copied");
+    assert_eq!(fs::read_to_string(dst.join("b.dotr-tmp"))?, "precious");
+    Ok(())
+}
+
+// ── output ──────────────────────────────────────────────────────────
+
+#[test]
+fn plan_entries_have_kinds() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    std::os::unix::fs::symlink("a", src.join("b"))?;
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+
+    let kinds: Vec<_> = super::Dotr::new()
+        .plan_link(src, dst)?
+        .entries
+        .iter()
+        .map(|e| e.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            Some(EntryKind::File),
+            Some(EntryKind::Symlink),
+            Some(EntryKind::DirLink)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn apply_reports_entries_as_they_are_done() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    let plan = super::Dotr::new().plan_link(src, dst)?;
+
+    let mut done = vec![];
+    plan.apply_with(|entry, report_entry| {
+        // Applied already, before the next one is
+        assert!(entry.dst.symlink_metadata().is_ok());
+        assert!(
+            done.len() == 1 || dst.join("b").symlink_metadata().is_err(),
+            "reported after the whole plan"
+        );
+        done.push((entry.dst.clone(), report_entry.outcome));
+    })?;
+    assert_eq!(
+        done,
+        [
+            (dst.join("a"), Outcome::Created),
+            (dst.join("b"), Outcome::Created)
+        ]
+    );
+    Ok(())
+}
+
+// ── relative symlinks ───────────────────────────────────────────────
+
+#[test]
+fn relative_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let dotr = super::Dotr::new().set_relative();
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    fs::create_dir_all(src.join("nvim"))?;
+    write_dotr_config(&src.join("nvim"), "traverse = \"link\"")?;
+
+    dotr.link(&src, &dst)?;
+    for (dst, src) in [
+        (dst.join("d").join("a"), src.join("d").join("a")),
+        (dst.join("nvim"), src.join("nvim")),
+    ] {
+        let target = fs::read_link(&dst)?;
+        assert!(target.is_relative());
+        assert_eq!(dst.parent().unwrap().join(target).canonicalize()?, src);
+    }
+    assert!(dotr.status(&src, &dst)?.iter().all(StatusEntry::is_in_sync));
+    Ok(())
+}
+
+#[test]
+fn relative_and_absolute_links_are_equivalent() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    super::Dotr::new().set_relative().link(src, dst)?;
+
+    let report = super::Dotr::new().link(src, dst)?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 1);
+    assert!(super::Dotr::new().status(src, dst)?[0].is_in_sync());
+    assert_eq!(
+        super::Dotr::new().unlink(src, dst)?.count(Outcome::Removed),
+        1
+    );
+    assert!(dst.join("a").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn relative_links_through_symlinked_dir() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let dotr = super::Dotr::new().set_relative();
+
+    fs::create_dir_all(src.join("cfg"))?;
+    fs::write(src.join("cfg").join("rc"), "rc")?;
+    // The destination directory is deeper than it looks
+    fs::create_dir_all(dst.join("data").join("x").join("y").join("cfg"))?;
+    fs::create_dir_all(dst.join("home"))?;
+    std::os::unix::fs::symlink("../data/x/y/cfg", dst.join("home").join("cfg"))?;
+
+    let home = dst.join("home");
+    dotr.link(&src, &home)?;
+    assert_eq!(fs::read_to_string(home.join("cfg").join("rc"))?, "rc");
+    assert!(
+        dotr.status(&src, &home)?
+            .iter()
+            .all(StatusEntry::is_in_sync)
+    );
+    Ok(())
+}
+
+#[test]
+fn dangling_link_is_not_linked() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let dotr = super::Dotr::new().set_relative();
+
+    fs::create_dir_all(src.join("cfg"))?;
+    create_file(&src.join("cfg").join("rc"))?;
+    fs::create_dir_all(dst.join("data").join("x").join("y").join("cfg"))?;
+    fs::create_dir_all(dst.join("home"))?;
+    std::os::unix::fs::symlink("../data/x/y/cfg", dst.join("home").join("cfg"))?;
+    // What would be right, were `cfg` a plain directory
+    let home = dst.join("home");
+    let rc = home.join("cfg").join("rc");
+    let naive = Path::new("../../..")
+        .join(src.file_name().unwrap())
+        .join("cfg")
+        .join("rc");
+    std::os::unix::fs::symlink(naive, &rc)?;
+    assert!(fs::metadata(&rc).is_err());
+
+    let status = dotr.status(&src, &home)?;
+    assert!(matches!(status[0].state, EntryState::PointsElsewhere(_)));
+    let report = dotr.link(&src, &home)?;
+    assert_eq!(report.count(Outcome::AlreadyCorrect), 0);
+    Ok(())
+}
+
+// ── equivalent links ────────────────────────────────────────────────
+
+#[test]
+fn links_through_another_path_are_equivalent() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let alias = TempDir::new()?;
+    let alias = alias.path().join("dotfiles");
+    std::os::unix::fs::symlink(&src, &alias)?;
+
+    create_file(&src.join("a"))?;
+    std::os::unix::fs::symlink(alias.join("a"), dst.join("a"))?;
+
+    let dotr = super::Dotr::new();
+    assert_eq!(dotr.link(&src, &dst)?.count(Outcome::AlreadyCorrect), 1);
+    let entries = dotr.status(&src, &dst)?;
+    assert_eq!(entries[0].state, EntryState::Equivalent(alias.join("a")));
+    assert!(entries[0].is_in_sync());
+    assert_eq!(dotr.unlink(&src, &dst)?.count(Outcome::Removed), 1);
+    assert!(dst.join("a").symlink_metadata().is_err());
+    Ok(())
+}
+
+#[test]
+fn rewrite_equivalent_links() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path().canonicalize()?, dst.path().canonicalize()?);
+    let alias = TempDir::new()?;
+    let alias = alias.path().join("dotfiles");
+    std::os::unix::fs::symlink(&src, &alias)?;
+
+    create_file(&src.join("a"))?;
+    std::os::unix::fs::symlink(alias.join("a"), dst.join("a"))?;
+
+    let dotr = super::Dotr::new().set_rewrite_equivalent();
+    assert_eq!(dotr.link(&src, &dst)?.count(Outcome::Updated), 1);
+    assert_eq!(fs::read_link(dst.join("a"))?, src.join("a"));
+    assert_eq!(dotr.status(&src, &dst)?[0].state, EntryState::Linked);
+    Ok(())
+}
+
+// ── type collisions ─────────────────────────────────────────────────
+
+#[test]
+fn real_directories_are_never_removed() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    std::os::unix::fs::symlink("a", src.join("s"))?;
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+    for name in ["a", "s", "d"] {
+        fs::create_dir_all(dst.join(name))?;
+        create_file(&dst.join(name).join("keep"))?;
+    }
+
+    for dotr in [
+        stateful_dotr(),
+        stateful_dotr().set_force(),
+        stateful_dotr().set_backup(),
+        stateful_dotr().set_force().set_mode(Mode::Copy),
+        stateful_dotr().set_force().set_mode(Mode::Hardlink),
+        stateful_dotr().set_force().set_mode(Mode::Template),
+    ] {
+        let report = dotr.link(src, dst)?;
+        assert_eq!(report.count(Outcome::ConflictSkipped), 3);
+        assert!(
+            report
+                .entries
+                .iter()
+                .all(|e| e.conflict == Some(ConflictKind::DirectoryInTheWay))
+        );
+        for name in ["a", "s", "d"] {
+            assert!(dst.join(name).join("keep").exists());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn file_in_the_way_of_a_directory() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    fs::create_dir_all(src.join("d"))?;
+    create_file(&src.join("d").join("a"))?;
+    create_file(&src.join("b"))?;
+    fs::write(dst.join("d"), "not a directory")?;
+
+    for dotr in [
+        super::Dotr::new(),
+        super::Dotr::new().set_force(),
+        super::Dotr::new().set_backup(),
+    ] {
+        let report = dotr.link(src, dst)?;
+        assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+        let skipped = report.entries.iter().find(|e| e.conflict.is_some());
+        assert_eq!(skipped.unwrap().conflict, Some(ConflictKind::ParentNotDir));
+        assert_eq!(fs::read_to_string(dst.join("d"))?, "not a directory");
+    }
+    assert_is_link(&dst.join("b"), &src.join("b"));
+    Ok(())
+}
+
+#[test]
+fn force_replaces_file_with_linked_directory() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+    create_file(&dst.join("d"))?;
+
+    assert_eq!(
+        super::Dotr::new()
+            .link(src, dst)?
+            .count(Outcome::ConflictSkipped),
+        1
+    );
+    let report = super::Dotr::new().set_force().link(src, dst)?;
+    assert_eq!(report.count(Outcome::ForceReplaced), 1);
+    assert_is_link(&dst.join("d"), &src.join("d"));
+    Ok(())
+}
+
+#[test]
+fn force_dirs_backs_up_directories() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_backup().set_force_dirs();
+
+    create_file(&src.join("a"))?;
+    fs::create_dir_all(src.join("d"))?;
+    write_dotr_config(&src.join("d"), "traverse = \"link\"")?;
+    for name in ["a", "d"] {
+        fs::create_dir_all(dst.join(name))?;
+        create_file(&dst.join(name).join("keep"))?;
+    }
+
+    assert_eq!(dotr.link(src, dst)?.count(Outcome::BackedUp), 2);
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert_is_link(&dst.join("d"), &src.join("d"));
+
+    assert_eq!(dotr.restore(dst, None)?.count(Outcome::Restored), 2);
+    for name in ["a", "d"] {
+        assert!(dst.join(name).symlink_metadata()?.is_dir());
+        assert!(dst.join(name).join("keep").exists());
+    }
+    assert!(!src.join("d").join("keep").exists());
+    Ok(())
+}
+
+// ── errors ──────────────────────────────────────────────────────────
+
+#[test]
+fn io_errors_carry_the_path() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    let plan = super::Dotr::new().plan_link(src, dst)?;
+    // Appears after planning, so creating the link fails
+    create_file(&dst.join("a"))?;
+
+    let err = plan.apply().unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::Io { path, op: "create symlink", source }
+            if path == &dst.join("a") && source.kind() == io::ErrorKind::AlreadyExists
+    ));
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::AlreadyExists);
+    Ok(())
+}
+
+#[test]
+fn backups_never_overwrite_earlier_ones() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let dotr = super::Dotr::new().set_backup();
+    let planned_backup = |plan: &super::Plan| match &plan.entries[0].steps[0].action {
+        Action::MoveToBackup { backup, .. } => backup.clone(),
+        action => panic!("unexpected {action}"),
+    };
+
+    create_file(&src.join("a"))?;
+    fs::write(dst.join("a"), "first")?;
+    let first = planned_backup(&dotr.plan_link(src, dst)?);
+    dotr.link(src, dst)?;
+
+    fs::remove_file(dst.join("a"))?;
+    fs::write(dst.join("a"), "second")?;
+    let second = planned_backup(&dotr.plan_link(src, dst)?);
+    assert_ne!(first.parent(), second.parent());
+    dotr.link(src, dst)?;
+    assert_eq!(fs::read_to_string(&first)?, "first");
+    assert_eq!(fs::read_to_string(&second)?, "second");
+
+    // The latest one is restored by default
+    dotr.restore(dst, None)?;
+    assert_eq!(fs::read_to_string(dst.join("a"))?, "second");
+    Ok(())
+}
+
+// ── interactive ─────────────────────────────────────────────────────
+
+/// Resolves conflicts as scripted, by destination file name
+struct Scripted(Vec<(&'static str, Resolution)>);
+
+impl Resolver for Scripted {
+    fn resolve(&mut self, conflict: &Conflict) -> Resolution {
+        let name = conflict.dst.file_name().unwrap();
+        self.0
+            .iter()
+            .find(|(n, _)| name == *n)
+            .expect("conflict is scripted")
+            .1
+    }
+}
+
+#[test]
+fn resolver_decides_every_conflict() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    for name in ["a", "b", "c", "d"] {
+        fs::write(src.join(name), "source")?;
+        fs::write(dst.join(name), "local edits")?;
+    }
+    let report = super::Dotr::new()
+        .set_resolver(Scripted(vec![
+            ("a", Resolution::Overwrite),
+            ("b", Resolution::Backup),
+            ("c", Resolution::Skip),
+            ("d", Resolution::Adopt),
+        ]))
+        .link(src, dst)?;
+
+    assert_eq!(report.count(Outcome::ForceReplaced), 1);
+    assert_eq!(report.count(Outcome::BackedUp), 1);
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    assert_eq!(report.count(Outcome::Adopted), 1);
+    assert_is_link(&dst.join("a"), &src.join("a"));
+    assert_is_link(&dst.join("b"), &src.join("b"));
+    let backups: Vec<_> = fs::read_dir(dst.join(".dotr-backup"))?.collect();
+    let backup = backups[0].as_ref().unwrap().path();
+    assert_eq!(fs::read_to_string(backup.join("b"))?, "local edits");
+    assert_eq!(fs::read_to_string(dst.join("c"))?, "local edits");
+    assert!(!dst.join("c").is_symlink());
+    assert_is_link(&dst.join("d"), &src.join("d"));
+    assert_eq!(fs::read_to_string(src.join("d"))?, "local edits");
+    Ok(())
+}
+
+#[test]
+fn resolver_cant_adopt_symlinks() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&dst.join("elsewhere"))?;
+    std::os::unix::fs::symlink(dst.join("elsewhere"), dst.join("a"))?;
+    let report = super::Dotr::new()
+        .set_resolver(Scripted(vec![("a", Resolution::Adopt)]))
+        .link(src, dst)?;
+
+    assert_eq!(report.count(Outcome::ConflictSkipped), 1);
+    assert_is_link(&dst.join("a"), &dst.join("elsewhere"));
+    Ok(())
+}
+
+#[test]
+fn prompt_applies_capital_answers_to_all() {
+    use super::prompt::Prompt;
+
+    let conflict = |adoptable| Conflict {
+        src: Path::new("/src/a"),
+        dst: Path::new("/dst/a"),
+        adoptable,
+    };
+    // Not adoptable, and unknown answers are asked again
+    let mut prompt = Prompt::new(io::Cursor::new("a\nx\nb\nO\n"));
+    assert_eq!(prompt.resolve(&conflict(false)), Resolution::Backup);
+    assert_eq!(prompt.resolve(&conflict(false)), Resolution::Overwrite);
+    assert_eq!(prompt.resolve(&conflict(true)), Resolution::Overwrite);
+
+    let mut prompt = Prompt::new(io::Cursor::new("a\n"));
+    assert_eq!(prompt.resolve(&conflict(true)), Resolution::Adopt);
+    // Out of answers
+    assert_eq!(prompt.resolve(&conflict(true)), Resolution::Skip);
+    assert_eq!(prompt.resolve(&conflict(true)), Resolution::Skip);
+}
+
+#[test]
+fn dotr_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    assert_send_sync(&super::Dotr::new().set_resolver(Scripted(vec![])));
+}
+
+#[test]
+fn resolver_not_asked_when_forced() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    create_file(&dst.join("a"))?;
+    let report = super::Dotr::new()
+        .set_force()
+        .set_resolver(Scripted(vec![]))
+        .link(src, dst)?;
+
+    assert_eq!(report.count(Outcome::ForceReplaced), 1);
+    Ok(())
+}
+
+// ── output ──────────────────────────────────────────────────────────
+
+/// What `print` prints as JSON values: the document in JSON, every line in
+/// NDJSON
+fn printed(output: Output, print: impl FnOnce(&mut Printer<Vec<u8>>)) -> Vec<serde_json::Value> {
+    let mut printer = Printer::to(output, vec![]);
+    print(&mut printer);
+    serde_json::Deserializer::from_slice(&printer.finish())
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+/// Keys of a JSON object, sorted
+fn keys(value: &serde_json::Value) -> Vec<&str> {
+    value
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect()
+}
+
+#[test]
+fn output_plan_records() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+    let entry_keys = [
+        "actions", "conflict", "dst", "error", "kind", "outcome", "src",
+    ];
+
+    create_file(&src.join("a"))?;
+    create_file(&src.join("b"))?;
+    create_file(&dst.join("b"))?;
+    let plan = super::Dotr::new().plan_link(src, dst)?;
+    let report = plan.report();
+
+    let json = printed(Output::Json, |out| out.plan(&plan, &report, true));
+    let [document] = &json[..] else {
+        panic!("expected a single document");
+    };
+    assert_eq!(keys(document), ["entries", "error", "summary"]);
+    let entries = document["entries"].as_array().unwrap();
+    assert_eq!(keys(&entries[0]), entry_keys);
+    assert_eq!(entries[0]["outcome"], "created");
+    assert_eq!(entries[0]["kind"], "file");
+    assert_eq!(entries[0]["actions"][0]["action"], "create-symlink");
+    assert_eq!(entries[1]["outcome"], "conflict-skipped");
+    assert_eq!(entries[1]["conflict"], "not-ours");
+    assert_eq!(document["summary"]["created"], 1);
+    assert_eq!(document["summary"]["conflict-skipped"], 1);
+    assert!(document["error"].is_null());
+
+    let ndjson = printed(Output::Ndjson, |out| out.plan(&plan, &report, true));
+    assert_eq!(ndjson.len(), 3);
+    for line in &ndjson[..2] {
+        assert_eq!(line["type"], "entry");
+        let mut expected = entry_keys.to_vec();
+        expected.push("type");
+        expected.sort();
+        assert_eq!(keys(line), expected);
+    }
+    assert_eq!(ndjson[2]["type"], "summary");
+    assert_eq!(ndjson[2]["created"], 1);
+    Ok(())
+}
+
+#[test]
+fn output_status_records() -> io::Result<()> {
+    let (src, dst) = setup();
+    let (src, dst) = (src.path(), dst.path());
+
+    create_file(&src.join("a"))?;
+    let entries = super::Dotr::new().status(src, dst)?;
+
+    let json = printed(Output::Json, |out| out.status(&entries));
+    let entry = &json[0]["entries"][0];
+    assert_eq!(
+        keys(entry),
+        ["dst", "in-sync", "kind", "src", "state", "target"]
+    );
+    assert_eq!(entry["in-sync"], false);
+    assert_eq!(
+        json[0]["summary"],
+        serde_json::json!({ "in-sync": 0, "out-of-sync": 1 })
+    );
+
+    let ndjson = printed(Output::Ndjson, |out| out.status(&entries));
+    assert_eq!(ndjson[0]["type"], "entry");
+    assert_eq!(
+        ndjson[1],
+        serde_json::json!({ "type": "summary", "in-sync": 0, "out-of-sync": 1 })
+    );
+    Ok(())
+}
+
+#[test]
+fn output_error_records() -> io::Result<()> {
+    let src = TempDir::new()?;
+    let missing = src.path().join("missing");
+    let error = super::Dotr::new()
+        .plan_link(src.path(), &missing)
+        .unwrap_err();
+    let error = anyhow::Error::from(error).context("Can't link");
+
+    let json = printed(Output::Json, |out| out.error(&error));
+    let record = &json[0]["error"];
+    assert_eq!(keys(record), ["causes", "kind", "message", "path"]);
+    assert_eq!(record["kind"], "destination-missing");
+    assert_eq!(record["path"], missing.to_str().unwrap());
+    assert_eq!(record["message"], "Can't link");
+    assert_eq!(record["causes"][0], error.root_cause().to_string());
+
+    let error = anyhow::anyhow!("Not from dotr");
+    let ndjson = printed(Output::Ndjson, |out| out.error(&error));
+    assert_eq!(
+        ndjson[..],
+        [serde_json::json!({
+            "type": "error",
+            "kind": "other",
+            "path": null,
+            "message": "Not from dotr",
+            "causes": [],
+        })]
+    );
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+use tracing::{debug, warn};
+
+use crate::Result;
+use crate::error::IoContext;
+
+/// Separates a source name from the conditions of the variant, e.g.
+/// `.bashrc##host.laptop`
+pub(crate) const VARIANT_SEPARATOR: &str = "##";
+
+/// What variants are selected for
+pub(crate) struct Selector {
+    pub(crate) hostname: Option<String>,
+    pub(crate) profiles: Vec<String>,
+}
+
+impl Selector {
+    /// How specific a match `conditions` (e.g. `host.laptop,profile.work`)
+    /// are, or `None` if they don't all hold
+    ///
+    /// Host conditions are more specific than profile ones.
+    fn score(&self, name: &str, conditions: &str) -> Option<u32> {
+        let mut score = 0;
+        for condition in conditions.split(',') {
+            score += match condition.split_once('.') {
+                Some(("host", host)) if self.hostname.as_deref() == Some(host) => 2,
+                Some(("profile", profile)) if self.profiles.iter().any(|p| p == profile) => 1,
+                Some(("host" | "profile", _)) => return None,
+                _ => {
+                    warn!(name, condition, "Unknown variant condition");
+                    return None;
+                }
+            };
+        }
+        Some(score)
+    }
+
+    /// Does a directory restricted to `hosts` and `profiles` (by its `.dotr`)
+    /// apply?
+    pub(crate) fn applies(&self, hosts: &[String], profiles: &[String]) -> bool {
+        (hosts.is_empty() || self.hostname.as_ref().is_some_and(|h| hosts.contains(h)))
+            && (profiles.is_empty() || self.profiles.iter().any(|p| profiles.contains(p)))
+    }
+
+    /// Variants among the entries of `dir`
+    ///
+    /// Maps the name of every entry that has variants, including the plain
+    /// one, to the name it's to be installed as if it's the best match, or
+    /// `None` if it's to be skipped.
+    pub(crate) fn select(&self, dir: &Path) -> Result<HashMap<OsString, Option<OsString>>> {
+        // base name -> (name, score) of every candidate
+        let mut candidates: BTreeMap<String, Vec<(String, Option<u32>)>> = BTreeMap::new();
+        for entry in fs::read_dir(dir).at(dir, "read directory")? {
+            let Ok(name) = entry.at(dir, "read directory")?.file_name().into_string() else {
+                continue;
+            };
+            let (base, score) = match name.split_once(VARIANT_SEPARATOR) {
+                Some((base, conditions)) => (base.to_owned(), self.score(&name, conditions)),
+                None => (name.clone(), Some(0)),
+            };
+            candidates.entry(base).or_default().push((name, score));
+        }
+
+        let mut selected = HashMap::new();
+        for (base, names) in candidates {
+            if names
+                .iter()
+                .all(|(name, _)| !name.contains(VARIANT_SEPARATOR))
+            {
+                continue;
+            }
+            // Highest score, then first name, to be deterministic
+            let best = names
+                .iter()
+                .filter_map(|(name, score)| Some((score.as_ref()?, name)))
+                .min_by_key(|(score, name)| (Reverse(**score), *name))
+                .map(|(_, name)| name.clone());
+            debug!(path = %dir.join(&base).display(), best = ?best, "Selected variant");
+            for (name, _) in &names {
+                let install_as = (Some(name) == best.as_ref()).then(|| base.clone().into());
+                selected.insert(name.into(), install_as);
+            }
+        }
+        Ok(selected)
+    }
+}